@@ -1,6 +1,14 @@
 use std::error::Error;
 
-pub trait DataHolderError: Error {}
+pub trait DataHolderError: Error {
+    /// the data in the holder was changed, everything received before is not valid anymore
+    fn is_resource_changed(&self) -> bool {
+        false
+    }
+}
+
+/// received data with its bounds
+pub type BoundedData<C> = (C, (usize, usize));
 
 pub trait DataHolder {
     type DataType;
@@ -8,6 +16,11 @@ pub trait DataHolder {
     type E: DataHolderError;
 
     fn request(&mut self, bounds: (usize, usize)) -> Result<(), Self::E>;
-    fn get_response(&mut self) -> Result<Option<(Self::DataContainer, (usize, usize))>, Self::E>;
+    fn get_response(&mut self) -> Result<Option<BoundedData<Self::DataContainer>>, Self::E>;
     fn get_data_len(&self) -> usize;
+
+    /// start over after the data was changed, the length of the data can be different after it
+    fn restart(&mut self) -> Result<(), Self::E> {
+        Ok(())
+    }
 }
@@ -11,10 +11,11 @@ pub trait Manager {
     fn move_data(self) -> Vec<u8>;
 
     fn ready(&self) -> bool {
-        if let Some(ref first_node) = self.get_filled_list().head {
-            if first_node.begin == 0 && first_node.end == self.get_data().len() {
-                return true;
-            }
+        if let Some(ref first_node) = self.get_filled_list().head
+            && first_node.begin == 0
+            && first_node.end == self.get_data().len()
+        {
+            return true;
         }
         false
     }
@@ -24,6 +25,14 @@ pub trait Manager {
 
     fn receive(&mut self, chunk: Vec<u8>, chunk_bounds: (usize, usize))
     -> Result<(), ManagerError>;
+
+    /// forget all received data, used when the data changed in the data holder
+    fn restart(&mut self, data_len: usize)
+    where
+        Self: Sized,
+    {
+        *self = Self::init(data_len);
+    }
 }
 
 pub mod smart_manager {
@@ -84,12 +93,15 @@ pub mod errors {
     #[derive(Debug)]
     pub enum ManagerError {
         TheDataIsFilled,
+        /// the data holder reported, that the data was changed and the manager must be restarted
+        ResourceChanged,
     }
 
     impl std::fmt::Display for ManagerError {
         fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
             match self {
                 Self::TheDataIsFilled => write!(f, "the data is already filled"),
+                Self::ResourceChanged => write!(f, "the data was changed in the data holder"),
             }
         }
     }
@@ -29,7 +29,11 @@ pub mod errors {
         MW::Data: DataHolder<E = E>,
     {
         fn from(value: E) -> Self {
-            Self::DataHolderError(value)
+            if value.is_resource_changed() {
+                Self::ManagerError(ManagerError::ResourceChanged)
+            } else {
+                Self::DataHolderError(value)
+            }
         }
     }
 
@@ -54,12 +58,12 @@ pub trait ManagerWrapper<ManagerT: Manager> {
 
     fn extra_handle_response(
         &mut self,
-        data: Vec<<Self::Data as DataHolder>::DataType>,
-        requested_bounds: (usize, usize),
+        _data: Vec<<Self::Data as DataHolder>::DataType>,
+        _requested_bounds: (usize, usize),
     ) {
     }
 
-    fn process_request_chunks(&mut self, request_answer: Vec<Chunk<usize>>) {}
+    fn process_request_chunks(&mut self, _request_answer: Vec<Chunk<usize>>) {}
 
     fn send_request(&mut self) -> Result<(), ManagerWrapperError<ManagerT, Self>>
     where
@@ -79,12 +83,25 @@ pub trait ManagerWrapper<ManagerT: Manager> {
         request_answer
             .iter()
             .try_for_each(|chunk| self.get_data_holder_mut().request((chunk.begin, chunk.end)))
-            .map_err(|err| Into::<ManagerWrapperError<ManagerT, Self>>::into(err))?;
+            .map_err(Into::<ManagerWrapperError<ManagerT, Self>>::into)?;
 
         self.process_request_chunks(request_answer);
         Ok(())
     }
 
+    /// restart both the data holder and the manager after the data was changed
+    fn restart(&mut self) -> Result<(), ManagerWrapperError<ManagerT, Self>>
+    where
+        Self: Sized,
+    {
+        self.get_data_holder_mut().restart()?;
+
+        let data_len = self.get_data_holder().get_data_len();
+        self.get_manager_mut().restart(data_len);
+
+        Ok(())
+    }
+
     fn handle_response(
         &mut self,
         data: Vec<<Self::Data as DataHolder>::DataType>,
@@ -1,19 +1,86 @@
 pub mod path {
-    #[derive(Debug, Clone)]
+    use crate::uri::{errors::UriError, percent, query::Query};
+
+    /// Origin-form of the request target: absolute path with an optional query
+    #[derive(Debug, Clone, PartialEq, Eq)]
     pub struct Path {
+        /// normalized, still percent encoded path
         pub path: String,
+        pub query: Option<Query>,
     }
 
     impl Path {
-        pub fn new(path: String) -> Result<Self, ()> {
-            Ok(Self {
-                path: Self::create_with_cheking(path)?,
-            })
+        pub fn new(path: String) -> Result<Self, UriError> {
+            let (path, query) = Self::create_with_cheking(path)?;
+            Ok(Self { path, query })
+        }
+
+        fn create_with_cheking(path: String) -> Result<(String, Option<Query>), UriError> {
+            if path.is_empty() {
+                return Err(UriError::Empty);
+            }
+
+            if path.contains('#') {
+                return Err(UriError::Fragment);
+            }
+
+            let (raw_path, raw_query) = match path.split_once('?') {
+                Some((raw_path, raw_query)) => (raw_path, Some(raw_query)),
+                None => (path.as_str(), None),
+            };
+
+            if !raw_path.starts_with('/') {
+                // the string is not empty, so there is a first char
+                return Err(UriError::InvalidCharacter(0, path.chars().next().unwrap()));
+            }
+
+            let normalized = percent::validate(raw_path, 0, |b| percent::is_pchar(b) || b == b'/')?;
+
+            let query = match raw_query {
+                Some(raw_query) => {
+                    percent::validate(raw_query, raw_path.len() + 1, percent::is_query_char)?;
+                    Some(Query::parse(raw_query)?)
+                }
+                None => None,
+            };
+
+            Ok((Self::remove_dot_segments(&normalized), query))
+        }
+
+        /// RFC 3986 5.2.4, segments which would go above the root are dropped
+        fn remove_dot_segments(path: &str) -> String {
+            let mut output: Vec<&str> = vec![];
+
+            let mut segments = path[1..].split('/').peekable();
+            while let Some(segment) = segments.next() {
+                let is_last = segments.peek().is_none();
+                match segment {
+                    "." => {
+                        if is_last {
+                            output.push("");
+                        }
+                    }
+                    ".." => {
+                        output.pop();
+                        if is_last {
+                            output.push("");
+                        }
+                    }
+                    segment => output.push(segment),
+                }
+            }
+
+            format!("/{}", output.join("/"))
+        }
+
+        pub fn with_query(mut self, query: Query) -> Self {
+            self.query = Some(query);
+            self
         }
 
-        fn create_with_cheking(path: String) -> Result<String, ()> {
-            //TODO: check the path
-            Ok(path)
+        /// percent decoded path
+        pub fn decoded(&self) -> Result<String, UriError> {
+            percent::decode(&self.path)
         }
     }
 
@@ -21,17 +88,56 @@ pub mod path {
         fn default() -> Self {
             Self {
                 path: "/".to_string(),
+                query: None,
+            }
+        }
+    }
+
+    impl std::str::FromStr for Path {
+        type Err = UriError;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            Self::new(s.to_string())
+        }
+    }
+
+    impl std::fmt::Display for Path {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match &self.query {
+                Some(query) => write!(f, "{}?{}", self.path, query),
+                None => write!(f, "{}", self.path),
             }
         }
     }
 }
 
 pub mod header {
-    #[derive(PartialEq, Eq, Hash, Clone, Debug)]
+    use std::hash::{Hash, Hasher};
+
+    /// Field names are case-insensitive (RFC 9110 5.1), the name is kept as it was given and compared without the case
+    #[derive(Clone, Debug)]
     pub struct HeaderName {
         pub name: String,
     }
 
+    impl PartialEq for HeaderName {
+        fn eq(&self, other: &Self) -> bool {
+            self.name.eq_ignore_ascii_case(&other.name)
+        }
+    }
+
+    impl Eq for HeaderName {}
+
+    impl Hash for HeaderName {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.name
+                .bytes()
+                .for_each(|b| state.write_u8(b.to_ascii_lowercase()));
+            // like str, so a name is not a prefix of another one
+            state.write_u8(0xff);
+        }
+    }
+
     impl From<String> for HeaderName {
         fn from(value: String) -> Self {
             Self { name: value }
@@ -73,7 +179,179 @@ pub mod message {
     pub trait HttpMessage {
         fn get_start_line(&self) -> String;
         fn get_headers(&self) -> &HashMap<HeaderName, HeaderValue>;
-        fn get_body(&self) -> Vec<u8>;
+        fn get_body(&self) -> &[u8];
+    }
+}
+
+pub mod errors {
+    use std::{error::Error, fmt::Display};
+
+    /// maximum amount of bytes of the input, which is kept in the error
+    pub const SNIPPET_LEN: usize = 32;
+
+    pub fn snippet(bytes: &[u8]) -> String {
+        String::from_utf8_lossy(&bytes[..bytes.len().min(SNIPPET_LEN)]).into_owned()
+    }
+
+    /// Error of http message parsing. Each variant contains the byte offset in the message, where the error occurred, and a snippet of the input.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum ParseError {
+        BadStatusLine {
+            offset: usize,
+            snippet: String,
+        },
+        InvalidStatusCode {
+            offset: usize,
+            snippet: String,
+        },
+        MalformedHeader {
+            offset: usize,
+            snippet: String,
+        },
+        NonUtf8Header {
+            offset: usize,
+            snippet: String,
+        },
+        MissingBodySeparator {
+            offset: usize,
+            snippet: String,
+        },
+        /// framing headers are invalid, contradict each other or the body
+        FramingMismatch {
+            offset: usize,
+            snippet: String,
+        },
+        StatusLineTooLong {
+            offset: usize,
+            snippet: String,
+        },
+        TooManyHeaders {
+            offset: usize,
+            snippet: String,
+        },
+        /// either a single header line or the whole header section exceeds the limit
+        HeaderTooLarge {
+            offset: usize,
+            snippet: String,
+        },
+        BodyTooLarge {
+            offset: usize,
+            snippet: String,
+        },
+        /// the body is shorter than `Content-Length`, only reported if the limits are strict about it
+        ContentLengthMismatch {
+            offset: usize,
+            snippet: String,
+        },
+        /// the underlying reader failed, snippet is the message of the io error
+        Io {
+            offset: usize,
+            kind: std::io::ErrorKind,
+            snippet: String,
+        },
+    }
+
+    impl ParseError {
+        pub fn io(offset: usize, err: std::io::Error) -> Self {
+            Self::Io {
+                offset,
+                kind: err.kind(),
+                snippet: err.to_string(),
+            }
+        }
+
+        pub fn offset(&self) -> usize {
+            match self {
+                Self::BadStatusLine { offset, .. }
+                | Self::InvalidStatusCode { offset, .. }
+                | Self::MalformedHeader { offset, .. }
+                | Self::NonUtf8Header { offset, .. }
+                | Self::MissingBodySeparator { offset, .. }
+                | Self::FramingMismatch { offset, .. }
+                | Self::StatusLineTooLong { offset, .. }
+                | Self::TooManyHeaders { offset, .. }
+                | Self::HeaderTooLarge { offset, .. }
+                | Self::BodyTooLarge { offset, .. }
+                | Self::ContentLengthMismatch { offset, .. }
+                | Self::Io { offset, .. } => *offset,
+            }
+        }
+
+        pub fn snippet(&self) -> &str {
+            match self {
+                Self::BadStatusLine { snippet, .. }
+                | Self::InvalidStatusCode { snippet, .. }
+                | Self::MalformedHeader { snippet, .. }
+                | Self::NonUtf8Header { snippet, .. }
+                | Self::MissingBodySeparator { snippet, .. }
+                | Self::FramingMismatch { snippet, .. }
+                | Self::StatusLineTooLong { snippet, .. }
+                | Self::TooManyHeaders { snippet, .. }
+                | Self::HeaderTooLarge { snippet, .. }
+                | Self::BodyTooLarge { snippet, .. }
+                | Self::ContentLengthMismatch { snippet, .. }
+                | Self::Io { snippet, .. } => snippet,
+            }
+        }
+    }
+
+    impl Display for ParseError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let description = match self {
+                Self::BadStatusLine { .. } => "bad status line",
+                Self::InvalidStatusCode { .. } => "invalid status code",
+                Self::MalformedHeader { .. } => "malformed header",
+                Self::NonUtf8Header { .. } => "header is not valid utf8",
+                Self::MissingBodySeparator { .. } => "there is no empty line after the headers",
+                Self::FramingMismatch { .. } => "message framing mismatch",
+                Self::StatusLineTooLong { .. } => "status line is too long",
+                Self::TooManyHeaders { .. } => "too many headers",
+                Self::HeaderTooLarge { .. } => "headers are too large",
+                Self::BodyTooLarge { .. } => "body is too large",
+                Self::ContentLengthMismatch { .. } => "body is shorter than content length",
+                Self::Io { .. } => "io error",
+            };
+
+            write!(
+                f,
+                "{} at byte {}: {:?}",
+                description,
+                self.offset(),
+                self.snippet()
+            )
+        }
+    }
+
+    impl Error for ParseError {}
+}
+
+pub mod limits {
+    /// Limits applied while parsing a message, they protect from hostile or broken peers
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct ParseLimits {
+        /// without the line ending
+        pub max_status_line_len: usize,
+        pub max_header_count: usize,
+        /// length of a single header line without the line ending
+        pub max_header_line_len: usize,
+        /// size of the whole header section including line endings
+        pub max_headers_size: usize,
+        pub max_body_size: usize,
+        /// reject bodies which are shorter than `Content-Length`
+        pub strict_content_length: bool,
+    }
+
+    impl Default for ParseLimits {
+        fn default() -> Self {
+            Self {
+                max_status_line_len: 8 * 1024,
+                max_header_count: 100,
+                max_header_line_len: 8 * 1024,
+                max_headers_size: 64 * 1024,
+                max_body_size: 256 * 1024 * 1024,
+                strict_content_length: false,
+            }
+        }
     }
 }
 
@@ -81,9 +359,63 @@ pub mod response {
     use crate::serialize::Deserialize;
 
     use super::*;
+    use errors::{ParseError, snippet};
     use header::{HeaderName, HeaderValue};
+    use limits::ParseLimits;
     use message::HttpMessage;
-    use std::{collections::HashMap, fmt::Display};
+    use std::{
+        collections::HashMap,
+        fmt::Display,
+        io::{BufRead, Read},
+    };
+
+    /// Gets the parts of a response while it is read, so they are available even if the reading fails later
+    pub trait ResponseObserver {
+        /// the head was read, the body of `response` is still empty
+        fn head(&mut self, _response: &HttpResponse) {}
+        /// bytes of the body starting at `offset`, for chunked bodies the offset is in the decoded body
+        fn body(&mut self, _offset: usize, _data: &[u8]) {}
+        /// true if the observer keeps the body by itself, then the body of the response stays empty
+        fn takes_body(&self) -> bool {
+            false
+        }
+    }
+
+    impl ResponseObserver for () {}
+
+    /// Body which is being read, it is not kept if the observer takes it
+    struct ReceivedBody {
+        data: Vec<u8>,
+        len: usize,
+        keep: bool,
+    }
+
+    /// Reads everything from the reader into the body and shows each read part to the observer. Returns the amount of read bytes
+    fn read_observed<R: BufRead>(
+        mut reader: R,
+        body: &mut ReceivedBody,
+        observer: &mut dyn ResponseObserver,
+    ) -> std::io::Result<usize> {
+        let mut read = 0;
+        loop {
+            let data = match reader.fill_buf() {
+                Ok([]) => return Ok(read),
+                Ok(data) => data,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+
+            observer.body(body.len, data);
+            if body.keep {
+                body.data.extend_from_slice(data);
+            }
+
+            let len = data.len();
+            body.len += len;
+            reader.consume(len);
+            read += len;
+        }
+    }
 
     #[derive(Debug)]
     pub struct HttpResponse {
@@ -92,6 +424,8 @@ pub mod response {
         pub result_string: String,
         pub headers: HashMap<HeaderName, HeaderValue>,
         pub body: Vec<u8>,
+        /// the connection was closed before the whole body was received
+        pub truncated: bool,
     }
 
     impl Display for HttpResponse {
@@ -116,6 +450,7 @@ pub mod response {
                 protocol: protocol.to_string(),
                 headers: HashMap::new(),
                 body: vec![],
+                truncated: false,
             }
         }
 
@@ -133,166 +468,701 @@ pub mod response {
 
     impl HttpMessage for HttpResponse {
         fn get_start_line(&self) -> String {
-            format!("{:?} {} {}", self.protocol, self.result, self.result_string,)
+            format!("{} {} {}", self.protocol, self.result, self.result_string)
         }
 
         fn get_headers(&self) -> &HashMap<HeaderName, HeaderValue> {
             &self.headers
         }
 
-        fn get_body(&self) -> Vec<u8> {
-            self.body.clone()
+        fn get_body(&self) -> &[u8] {
+            &self.body
+        }
+    }
+
+    impl HttpResponse {
+        fn parse_status_line(line: &[u8], offset: usize) -> Result<(&str, u16, &str), ParseError> {
+            let bad_status_line = || ParseError::BadStatusLine {
+                offset,
+                snippet: snippet(line),
+            };
+
+            let line = std::str::from_utf8(line).map_err(|_| bad_status_line())?;
+
+            let mut elements = line.splitn(3, ' ');
+
+            // the string will always have at least first element
+            let protocol = elements.next().unwrap();
+            if !protocol.starts_with("HTTP/") {
+                return Err(bad_status_line());
+            }
+
+            let code = elements.next().ok_or_else(bad_status_line)?;
+            let result = if code.len() == 3 && code.bytes().all(|b| b.is_ascii_digit()) {
+                // 3 digits always fit into u16
+                code.parse::<u16>().unwrap()
+            } else {
+                return Err(ParseError::InvalidStatusCode {
+                    offset: offset + protocol.len() + 1,
+                    snippet: snippet(code.as_bytes()),
+                });
+            };
+
+            // reason phrase can contain spaces and also can be empty
+            let result_string = elements.next().unwrap_or("");
+
+            Ok((protocol, result, result_string))
+        }
+
+        fn parse_header(line: &[u8], offset: usize) -> Result<(&str, &str), ParseError> {
+            let line = std::str::from_utf8(line).map_err(|err| ParseError::NonUtf8Header {
+                offset: offset + err.valid_up_to(),
+                snippet: snippet(line),
+            })?;
+
+            let malformed = || ParseError::MalformedHeader {
+                offset,
+                snippet: snippet(line.as_bytes()),
+            };
+
+            let (name, value) = line.split_once(':').ok_or_else(malformed)?;
+
+            // no whitespace is allowed between the name and the colon (RFC 9112 5.1)
+            if name.is_empty()
+                || !name
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+            {
+                return Err(malformed());
+            }
+
+            Ok((name, value.trim_matches([' ', '\t'])))
+        }
+
+        /// how the end of the body is determined (RFC 9112 6.3)
+        fn framing(
+            result: u16,
+            headers: &HashMap<HeaderName, HeaderValue>,
+            offset: usize,
+        ) -> Result<BodyFraming, ParseError> {
+            if (100..200).contains(&result) || result == 204 || result == 304 {
+                return Ok(BodyFraming::Empty);
+            }
+
+            let length = headers.get(&"Content-Length".into());
+
+            if let Some(encoding) = headers.get(&"Transfer-Encoding".into()) {
+                if length.is_some() {
+                    return Err(ParseError::FramingMismatch {
+                        offset,
+                        snippet: snippet(encoding.value.as_bytes()),
+                    });
+                }
+
+                // if chunked is not the final encoding, the body lasts until the end of the stream
+                let is_chunked = encoding
+                    .value
+                    .rsplit(',')
+                    .next()
+                    .is_some_and(|last| last.trim().eq_ignore_ascii_case("chunked"));
+
+                return Ok(if is_chunked {
+                    BodyFraming::Chunked
+                } else {
+                    BodyFraming::UntilClose
+                });
+            }
+
+            match length {
+                Some(length) => length
+                    .value
+                    .parse::<usize>()
+                    .map(BodyFraming::Length)
+                    .map_err(|_| ParseError::FramingMismatch {
+                        offset,
+                        snippet: snippet(length.value.as_bytes()),
+                    }),
+                None => Ok(BodyFraming::UntilClose),
+            }
+        }
+
+        /// framing of the body of this response
+        pub fn body_framing(&self) -> Result<BodyFraming, ParseError> {
+            Self::framing(self.result, &self.headers, 0)
+        }
+
+        /// Read chunked body (RFC 9112 7.1), trailers are skipped. Returns false if the stream ended before the last chunk
+        fn read_chunked<R: BufRead>(
+            lines: &mut LineReader<R>,
+            body: &mut ReceivedBody,
+            limits: &ParseLimits,
+            observer: &mut dyn ResponseObserver,
+        ) -> Result<bool, ParseError> {
+            loop {
+                let (size, offset) = match lines.next_line(limits.max_header_line_len)? {
+                    Line::Complete(line, offset) => (line, offset),
+                    Line::Eof => return Ok(false),
+                    Line::TooLong => {
+                        return Err(ParseError::FramingMismatch {
+                            offset: lines.offset,
+                            snippet: snippet(&lines.line),
+                        });
+                    }
+                };
+
+                // chunk extensions are ignored
+                let size = size.split(|&b| b == b';').next().unwrap_or(size);
+                let size = std::str::from_utf8(size)
+                    .ok()
+                    .and_then(|size| usize::from_str_radix(size.trim(), 16).ok())
+                    .ok_or_else(|| ParseError::FramingMismatch {
+                        offset,
+                        snippet: snippet(size),
+                    })?;
+
+                if size == 0 {
+                    break;
+                }
+
+                // the size comes from the peer, so the sum could overflow
+                if size > limits.max_body_size.saturating_sub(body.len) {
+                    return Err(ParseError::BodyTooLarge {
+                        offset,
+                        snippet: format!("{:x}", size),
+                    });
+                }
+
+                let read = read_observed((&mut *lines.reader).take(size as u64), body, observer)
+                    .map_err(|err| ParseError::io(lines.offset, err))?;
+                lines.offset += read;
+
+                if read < size {
+                    return Ok(false);
+                }
+
+                match lines.next_line(0)? {
+                    Line::Complete(_, _) => {}
+                    Line::Eof => return Ok(false),
+                    Line::TooLong => {
+                        return Err(ParseError::FramingMismatch {
+                            offset: lines.offset - lines.line.len(),
+                            snippet: snippet(&lines.line),
+                        });
+                    }
+                }
+            }
+
+            // trailer section ends with an empty line
+            loop {
+                match lines.next_line(limits.max_header_line_len)? {
+                    Line::Complete([], _) => return Ok(true),
+                    Line::Complete(_, _) => {}
+                    Line::Eof => return Ok(false),
+                    Line::TooLong => {
+                        return Err(ParseError::HeaderTooLarge {
+                            offset: lines.offset - lines.line.len(),
+                            snippet: snippet(&lines.line),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    /// How the end of the body is determined
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum BodyFraming {
+        /// the response can not have a body
+        Empty,
+        Length(usize),
+        Chunked,
+        /// the body lasts until the server closes the connection, so the connection can not be reused
+        UntilClose,
+    }
+
+    /// Reads a message line by line and keeps track of the offset in the message
+    struct LineReader<'a, R: BufRead> {
+        reader: &'a mut R,
+        offset: usize,
+        line: Vec<u8>,
+    }
+
+    enum Line<'b> {
+        /// the line without the line ending and its offset
+        Complete(&'b [u8], usize),
+        /// the stream ended before the line ending
+        Eof,
+        TooLong,
+    }
+
+    impl<'a, R: BufRead> LineReader<'a, R> {
+        fn new(reader: &'a mut R) -> Self {
+            Self {
+                reader,
+                offset: 0,
+                line: vec![],
+            }
+        }
+
+        /// reads at most `max_len` bytes plus the line ending, so a line without the end can not exhaust the memory
+        fn next_line(&mut self, max_len: usize) -> Result<Line<'_>, ParseError> {
+            self.line.clear();
+            let line_begin = self.offset;
+
+            let len = (&mut *self.reader)
+                .take(max_len as u64 + 2)
+                .read_until(b'\n', &mut self.line)
+                .map_err(|err| ParseError::io(line_begin, err))?;
+            self.offset += len;
+
+            match self.line.strip_suffix(b"\n") {
+                Some(line) => {
+                    let line = line.strip_suffix(b"\r").unwrap_or(line);
+                    if line.len() > max_len {
+                        Ok(Line::TooLong)
+                    } else {
+                        Ok(Line::Complete(line, line_begin))
+                    }
+                }
+                None if len > max_len => Ok(Line::TooLong),
+                None => Ok(Line::Eof),
+            }
         }
     }
 
     impl Deserialize for HttpResponse {
-        fn desrialize(buffer: Vec<u8>) -> Result<Self, String>
+        fn deserialize_with_limits<R: BufRead>(
+            reader: &mut R,
+            limits: &ParseLimits,
+        ) -> Result<Self, ParseError>
         where
             Self: Sized,
         {
-            //divide by lines
-            let mut current_begin_index = 0;
-            let mut lines = buffer.split(|&b| b == b'\n').map(|line| {
-                let line_len = line.len() + 1;
-                current_begin_index += line_len;
-                if !line.is_empty() && line[line.len() - 1] == b'\r' {
-                    (&line[..line.len() - 1], current_begin_index - line_len)
-                } else {
-                    (line, current_begin_index - line_len)
-                }
-            });
+            Self::deserialize_observed(reader, limits, &mut ())
+        }
+    }
+
+    impl HttpResponse {
+        /// Like [`Deserialize::deserialize_with_limits`], the observer gets the head and the parts of the body as soon as they are read
+        pub fn deserialize_observed<R: BufRead>(
+            reader: &mut R,
+            limits: &ParseLimits,
+            observer: &mut dyn ResponseObserver,
+        ) -> Result<Self, ParseError> {
+            let mut lines = LineReader::new(reader);
 
             // parse first line
-            let (protocol, result, result_string) = {
-                if let Some((first_line, _)) = lines.next() {
-                    let mut elements = first_line.split(|&b| b == b' ');
+            let mut response = match lines.next_line(limits.max_status_line_len)? {
+                Line::Complete(first_line, offset) => {
+                    let (protocol, result, result_string) =
+                        Self::parse_status_line(first_line, offset)?;
+                    HttpResponse::new(result, result_string, protocol)
+                }
+                Line::Eof => {
+                    return Err(ParseError::BadStatusLine {
+                        offset: 0,
+                        snippet: snippet(&lines.line),
+                    });
+                }
+                Line::TooLong => {
+                    return Err(ParseError::StatusLineTooLong {
+                        offset: 0,
+                        snippet: snippet(&lines.line),
+                    });
+                }
+            };
 
-                    // the string will always have at least first element
-                    let protocol = std::str::from_utf8(elements.next().unwrap())
-                        .map_err(|_| "utf8 error while parsing (element 1)")?;
+            let headers_begin = lines.offset;
+            loop {
+                let line_begin = lines.offset;
+                let header_too_large = |lines: &LineReader<R>| ParseError::HeaderTooLarge {
+                    offset: line_begin,
+                    snippet: snippet(&lines.line),
+                };
 
-                    let result = std::str::from_utf8(if let Some(val) = elements.next() {
-                        Ok(val)
-                    } else {
-                        Err("there is only one element in first string")
-                    }?)
-                    .map_err(|_| "utf8 error while parsing(element 2)")?
-                    .parse::<u16>()
-                    .map_err(|err| format!("{:?}", err))?;
-
-                    let result_string = std::str::from_utf8(if let Some(val) = elements.next() {
-                        Ok(val)
-                    } else {
-                        Err("there is only one element in first string")
-                    }?)
-                    .map_err(|_| "utf8 error while parsing(element 2)")?;
-
-                    if !elements.next().is_none() {
-                        // Err(format!(
-                        //     "to match arguments in {:?}",
-                        //     (protocol, result, result_string)
-                        // ))
-                        Ok((protocol, result, result_string))
-                    } else {
-                        Ok((protocol, result, result_string))
+                let (line, offset) = match lines.next_line(limits.max_header_line_len)? {
+                    Line::Complete(line, offset) => (line, offset),
+                    // there must be an empty line after the headers, even if the body is empty
+                    Line::Eof => {
+                        return Err(ParseError::MissingBodySeparator {
+                            offset: lines.offset,
+                            snippet: snippet(&lines.line),
+                        });
                     }
-                } else {
-                    Err("there is not first line".to_string())
+                    Line::TooLong => return Err(header_too_large(&lines)),
+                };
+
+                if line.is_empty() {
+                    #[cfg(debug_assertions)]
+                    println!("header amount: {}", response.headers.len());
+                    break;
                 }
-            }?;
-
-            let headers = {
-                let mut map = HashMap::new();
-                let mut header_amount = 0;
-                while let Some((line, _)) = lines.next() {
-                    if std::str::from_utf8(line)
-                        .map_err(|err| format!("{:?}", err))?
-                        .is_empty()
-                    {
-                        #[cfg(debug_assertions)]
-                        println!("header amount: {}", header_amount);
-                        break;
+
+                if response.headers.len() == limits.max_header_count {
+                    return Err(ParseError::TooManyHeaders {
+                        offset,
+                        snippet: snippet(line),
+                    });
+                }
+
+                let (name, value) = Self::parse_header(line, offset)?;
+
+                response
+                    .headers
+                    .insert(HeaderName::from(name), HeaderValue::from(value));
+
+                if lines.offset - headers_begin > limits.max_headers_size {
+                    return Err(header_too_large(&lines));
+                }
+            }
+
+            let body_offset = lines.offset;
+            observer.head(&response);
+            let mut body = ReceivedBody {
+                data: vec![],
+                len: 0,
+                keep: !observer.takes_body(),
+            };
+
+            // one byte more than allowed, to find out that the body is too large
+            let max_read = match Self::framing(response.result, &response.headers, body_offset)? {
+                BodyFraming::Empty => return Ok(response),
+                BodyFraming::Chunked => {
+                    let complete = Self::read_chunked(&mut lines, &mut body, limits, observer)?;
+                    response.body = body.data;
+
+                    if !complete && limits.strict_content_length {
+                        return Err(ParseError::FramingMismatch {
+                            offset: lines.offset,
+                            snippet: snippet(&lines.line),
+                        });
                     }
 
-                    let mut elements = line.split(|&b| b == b':');
-
-                    let header: Result<(&str, &str), String> = {
-                        let name = std::str::from_utf8(if let Some(val) = elements.next() {
-                            Ok(val)
-                        } else {
-                            Err("error while parsing headers name".to_string())
-                        }?)
-                        .map_err(|_err| "utf8 error while parsing(element 2)".to_string())?;
-
-                        let value = std::str::from_utf8(if let Some(val) = elements.next() {
-                            Ok(val)
-                        } else {
-                            Err("error while parsing headers value".to_string())
-                        }?)
-                        .map_err(|_err| "utf8 error while parsing(element 2)".to_string())?;
-
-                        // if let Some(next_element) = elements.next() {
-                        //     Err(format!(
-                        //         "too many elements in header. Element: {:?}",
-                        //         next_element
-                        //     ))
-                        // } else {
-                        //     Ok((name, value))
-                        // }
-                        Ok((name, value))
-                    };
-                    let header = header?;
+                    response.truncated = !complete;
+                    return Ok(response);
+                }
+                BodyFraming::Length(length) if length > limits.max_body_size => {
+                    return Err(ParseError::BodyTooLarge {
+                        offset: body_offset,
+                        snippet: length.to_string(),
+                    });
+                }
+                BodyFraming::Length(length) => length as u64,
+                BodyFraming::UntilClose => limits.max_body_size as u64 + 1,
+            };
+
+            // the body can be shorter than the content length, as server can drop the connection,
+            // it is up to the caller to decide what to do with partial data
+            read_observed(lines.reader.take(max_read), &mut body, observer)
+                .map_err(|err| ParseError::io(body_offset + body.len, err))?;
 
-                    #[cfg(debug_assertions)]
-                    println!("Header: {}:{}", header.0, header.1);
-
-                    map.insert(
-                        HeaderName {
-                            name: header.0.to_string(),
-                        },
-                        HeaderValue {
-                            value: header.1.to_string(),
-                        },
-                    );
-                    header_amount += 1;
+            if body.len > limits.max_body_size {
+                return Err(ParseError::BodyTooLarge {
+                    offset: body_offset + limits.max_body_size,
+                    snippet: snippet(body.data.get(limits.max_body_size..).unwrap_or_default()),
+                });
+            }
+
+            let received = body.len;
+            response.body = body.data;
+            if let BodyFraming::Length(length) =
+                Self::framing(response.result, &response.headers, body_offset)?
+                && received != length
+            {
+                if limits.strict_content_length {
+                    return Err(ParseError::ContentLengthMismatch {
+                        offset: body_offset + received,
+                        snippet: length.to_string(),
+                    });
                 }
-                Result::<HashMap<HeaderName, HeaderValue>, String>::Ok(map)
-            }?;
 
-            let body = if let Some(first_body_line) = lines.next() {
-                Ok(buffer[first_body_line.1..].to_vec())
-            } else {
-                Err("there is not body".to_string())
-            }?;
+                response.truncated = true;
+            }
+
+            Ok(response)
+        }
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let response = HttpResponse::desrialize(
+            b"HTTP/1.1 206 Partial Content\r\nContent-Length: 4\r\nHost: 127.0.0.1:8080\r\n\r\nbody"
+                .to_vec(),
+        )
+        .unwrap();
 
-            // let body = lines.try_fold(Vec::new(), |mut vec, (line, _)| {
-            //     vec.extend(line);
-            //     Result::<Vec<u8>, String>::Ok(vec)
-            // })?;
+        assert_eq!(response.result, 206);
+        assert_eq!(response.result_string, "Partial Content");
+        assert_eq!(
+            response.headers.get(&"Host".into()).unwrap().value,
+            "127.0.0.1:8080"
+        );
+        assert_eq!(response.body, b"body");
+    }
+
+    #[test]
+    fn test_deserialize_from_stream() {
+        use crate::serialize::Serialize;
 
-            Ok({
-                let mut response = HttpResponse::new(result, result_string, protocol);
+        let mut first = HttpResponse::new(206, "Partial Content", "HTTP/1.1");
+        first.add_header("Content-Length", "3");
+        first.body = b"abc".to_vec();
 
-                response.headers = headers;
+        let mut stream = vec![];
+        first.serialize_into(&mut stream).unwrap();
+        stream.extend(b"HTTP/1.1 200 OK\r\n\r\nrest of the stream");
 
-                response.body = body;
+        let mut reader = std::io::BufReader::new(stream.as_slice());
 
-                response
-            })
+        let response = HttpResponse::deserialize_from(&mut reader).unwrap();
+        assert_eq!(response.get_start_line(), "HTTP/1.1 206 Partial Content");
+        assert_eq!(response.get_body(), b"abc");
+
+        // the message without content length lasts until the end of the stream
+        let response = HttpResponse::deserialize_from(&mut reader).unwrap();
+        assert_eq!(response.result, 200);
+        assert_eq!(response.get_body(), b"rest of the stream");
+    }
+
+    #[test]
+    fn test_lowercase_headers() {
+        let mut stream: &[u8] =
+            b"HTTP/1.1 200 OK\r\ncontent-length: 3\r\nhost: 127.0.0.1\r\n\r\nabc\
+            HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n1\r\nd\r\n0\r\n\r\n";
+
+        // the next pipelined response is not swallowed by the first one
+        let response = HttpResponse::deserialize_from(&mut stream).unwrap();
+        assert_eq!(response.body_framing(), Ok(BodyFraming::Length(3)));
+        assert_eq!(response.body, b"abc");
+        assert_eq!(
+            response.headers.get(&"Host".into()).unwrap().value,
+            "127.0.0.1"
+        );
+
+        let response = HttpResponse::deserialize_from(&mut stream).unwrap();
+        assert_eq!(response.body_framing(), Ok(BodyFraming::Chunked));
+        assert_eq!(response.body, b"d");
+    }
+
+    #[test]
+    fn test_deserialize_framing() {
+        let mut stream: &[u8] = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+            4;ext=1\r\nWiki\r\n5\r\npedia\r\n0\r\nTrailer: x\r\n\r\n\
+            HTTP/1.1 204 No Content\r\n\r\n\
+            HTTP/1.1 206 Partial Content\r\nContent-Length: 10\r\n\r\n12345";
+
+        let response = HttpResponse::deserialize_from(&mut stream).unwrap();
+        assert_eq!(response.body, b"Wikipedia");
+        assert_eq!(response.body_framing(), Ok(BodyFraming::Chunked));
+        assert!(!response.truncated);
+
+        let response = HttpResponse::deserialize_from(&mut stream).unwrap();
+        assert_eq!(response.result, 204);
+        assert_eq!(response.body_framing(), Ok(BodyFraming::Empty));
+
+        let response = HttpResponse::deserialize_from(&mut stream).unwrap();
+        assert_eq!(response.body, b"12345");
+        assert!(response.truncated);
+
+        let response = HttpResponse::desrialize(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nWi".to_vec(),
+        )
+        .unwrap();
+        assert_eq!(response.body, b"Wi");
+        assert!(response.truncated);
+
+        assert!(matches!(
+            HttpResponse::desrialize(
+                b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n".to_vec()
+            ),
+            Err(ParseError::FramingMismatch { offset: 47, .. })
+        ));
+    }
+
+    #[test]
+    fn test_deserialize_observed() {
+        #[derive(Default)]
+        struct Parts {
+            status: Option<u16>,
+            body: Vec<(usize, Vec<u8>)>,
         }
+
+        impl ResponseObserver for Parts {
+            fn head(&mut self, response: &HttpResponse) {
+                self.status = Some(response.result);
+            }
+
+            fn body(&mut self, offset: usize, data: &[u8]) {
+                self.body.push((offset, data.to_vec()));
+            }
+        }
+
+        let mut parts = Parts::default();
+        let mut stream: &[u8] = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+            4\r\nWiki\r\n5\r\npedia\r\n0\r\n\r\n";
+        let response =
+            HttpResponse::deserialize_observed(&mut stream, &ParseLimits::default(), &mut parts)
+                .unwrap();
+        assert_eq!(response.body, b"Wikipedia");
+        assert_eq!(parts.status, Some(200));
+        assert_eq!(
+            parts.body,
+            vec![(0, b"Wiki".to_vec()), (4, b"pedia".to_vec())]
+        );
+
+        // the connection fails in the middle of the body, the part which came through was observed
+        struct FailingReader;
+
+        impl std::io::Read for FailingReader {
+            fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::ConnectionReset.into())
+            }
+        }
+
+        let failing = std::io::Read::chain(
+            &b"HTTP/1.1 206 Partial Content\r\nContent-Length: 10\r\n\r\n12345"[..],
+            FailingReader,
+        );
+        let mut parts = Parts::default();
+        assert!(matches!(
+            HttpResponse::deserialize_observed(
+                &mut std::io::BufReader::new(failing),
+                &ParseLimits::default(),
+                &mut parts
+            ),
+            Err(ParseError::Io { .. })
+        ));
+        assert_eq!(parts.status, Some(206));
+        assert_eq!(parts.body, vec![(0, b"12345".to_vec())]);
+
+        // the observer, which takes the body, gets it only once
+        struct Taking(Vec<u8>);
+
+        impl ResponseObserver for Taking {
+            fn body(&mut self, _offset: usize, data: &[u8]) {
+                self.0.extend_from_slice(data);
+            }
+
+            fn takes_body(&self) -> bool {
+                true
+            }
+        }
+
+        let mut taking = Taking(vec![]);
+        let mut stream: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\nWikipedia";
+        let response =
+            HttpResponse::deserialize_observed(&mut stream, &ParseLimits::default(), &mut taking)
+                .unwrap();
+        assert!(response.body.is_empty());
+        assert!(!response.truncated);
+        assert_eq!(taking.0, b"Wikipedia");
+    }
+
+    #[test]
+    fn test_deserialize_limits() {
+        let limits = ParseLimits {
+            max_status_line_len: 20,
+            max_header_count: 2,
+            max_header_line_len: 18,
+            max_headers_size: 24,
+            max_body_size: 4,
+            strict_content_length: true,
+        };
+        let error = |buffer: &[u8]| {
+            HttpResponse::deserialize_with_limits(&mut &buffer[..], &limits).unwrap_err()
+        };
+
+        assert!(matches!(
+            error(b"HTTP/1.1 206 Partial Content\r\n\r\n"),
+            ParseError::StatusLineTooLong { offset: 0, .. }
+        ));
+        assert!(matches!(
+            error(b"HTTP/1.1 200 OK\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n"),
+            ParseError::TooManyHeaders { offset: 29, .. }
+        ));
+        assert!(matches!(
+            error(b"HTTP/1.1 200 OK\r\nName: a very long value\r\n\r\n"),
+            ParseError::HeaderTooLarge { offset: 17, .. }
+        ));
+        // the line without the end must not be read completely
+        assert!(matches!(
+            error(&[b"HTTP/1.1 200 OK\r\nName: ".as_slice(), &[b'a'; 1024]].concat()),
+            ParseError::HeaderTooLarge { offset: 17, .. }
+        ));
+        assert!(matches!(
+            error(b"HTTP/1.1 200 OK\r\nName1: value1\r\nName2: value2\r\n\r\n"),
+            ParseError::HeaderTooLarge { offset: 32, .. }
+        ));
+        assert!(matches!(
+            error(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n12345"),
+            ParseError::BodyTooLarge { offset: 38, .. }
+        ));
+        assert!(matches!(
+            error(b"HTTP/1.1 200 OK\r\n\r\n12345"),
+            ParseError::BodyTooLarge { offset: 23, .. }
+        ));
+        assert!(matches!(
+            error(b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\n12"),
+            ParseError::ContentLengthMismatch { offset: 40, .. }
+        ));
+        // a huge chunk size must not overflow the check of the limit
+        assert!(matches!(
+            HttpResponse::deserialize_with_limits(
+                &mut &b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n1\r\na\r\nffffffffffffffff\r\n"[..],
+                &ParseLimits::default()
+            ),
+            Err(ParseError::BodyTooLarge { offset: 53, .. })
+        ));
+    }
+
+    #[test]
+    fn test_deserialize_errors() {
+        let error = |buffer: &[u8]| HttpResponse::desrialize(buffer.to_vec()).unwrap_err();
+
+        assert_eq!(
+            error(b"HTTP/1.1 2x6 Partial\r\n\r\n"),
+            ParseError::InvalidStatusCode {
+                offset: 9,
+                snippet: "2x6".to_string()
+            }
+        );
+        assert!(matches!(
+            error(b"garbage\r\n\r\n"),
+            ParseError::BadStatusLine { offset: 0, .. }
+        ));
+        assert_eq!(
+            error(b"HTTP/1.1 200 OK\r\nContent Length: 1\r\n\r\n"),
+            ParseError::MalformedHeader {
+                offset: 17,
+                snippet: "Content Length: 1".to_string()
+            }
+        );
+        assert!(matches!(
+            error(b"HTTP/1.1 200 OK\r\nName: \xff\r\n\r\n"),
+            ParseError::NonUtf8Header { offset: 23, .. }
+        ));
+        assert!(matches!(
+            error(b"HTTP/1.1 200 OK\r\nName: value\r\n"),
+            ParseError::MissingBodySeparator { offset: 30, .. }
+        ));
+        assert!(matches!(
+            error(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nbody"),
+            ParseError::FramingMismatch { offset: 40, .. }
+        ));
     }
 }
 
 pub mod request {
-    use std::collections::HashMap;
+    use std::{collections::HashMap, io::Write};
 
     use crate::serialize::Serialize;
 
     use super::*;
+    use crate::uri::target::RequestTarget;
     use header::{HeaderName, HeaderValue};
     use message::HttpMessage;
     use path::Path;
-    #[derive(Debug, Clone)]
+    #[derive(Debug, Clone, PartialEq, Eq)]
     pub enum HttpRequestMethod {
         GET,
         POST,
@@ -303,17 +1173,21 @@ pub mod request {
     #[derive(Debug, Clone)]
     pub struct HttpRequest {
         pub method: HttpRequestMethod,
-        pub request_target: Path,
+        pub request_target: RequestTarget,
         pub protocol: String,
         pub headers: HashMap<HeaderName, HeaderValue>,
         pub body: Vec<u8>,
     }
 
     impl HttpRequest {
-        pub fn new(method: HttpRequestMethod, request_target: Path, protocol: &str) -> Self {
+        pub fn new(
+            method: HttpRequestMethod,
+            request_target: impl Into<RequestTarget>,
+            protocol: &str,
+        ) -> Self {
             Self {
                 method,
-                request_target,
+                request_target: request_target.into(),
                 protocol: protocol.to_string(),
                 headers: HashMap::new(),
                 body: vec![],
@@ -336,7 +1210,7 @@ pub mod request {
         fn get_start_line(&self) -> String {
             format!(
                 "{:?} {} {}",
-                self.method, self.request_target.path, self.protocol
+                self.method, self.request_target, self.protocol
             )
         }
 
@@ -344,31 +1218,26 @@ pub mod request {
             &self.headers
         }
 
-        fn get_body(&self) -> Vec<u8> {
-            self.body.clone()
+        fn get_body(&self) -> &[u8] {
+            &self.body
         }
     }
 
     impl<T: HttpMessage> Serialize for T {
-        fn serialize(self) -> Vec<u8> {
-            let mut result = vec![];
+        fn serialize_into<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
             // ading a first line
-            result.extend(format!("{}\r\n", self.get_start_line()).as_bytes());
+            write!(writer, "{}\r\n", self.get_start_line())?;
 
             // adding headers
-            self.get_headers().into_iter().for_each(|(hn, hv)| {
-                result.extend(format!("{}: {}\r\n", hn.name, hv.value).as_bytes());
-            });
+            for (hn, hv) in self.get_headers() {
+                write!(writer, "{}: {}\r\n", hn.name, hv.value)?;
+            }
 
             //adding emtpty line
-
-            result.extend(b"\r\n");
+            writer.write_all(b"\r\n")?;
 
             //adding body
-
-            result.extend(self.get_body());
-
-            result
+            writer.write_all(self.get_body())
         }
     }
 
@@ -1,2 +1,3 @@
 pub mod http_messages;
 pub mod serialize;
+pub mod uri;
@@ -1,11 +1,56 @@
+use std::io::{BufRead, Write};
+
+use crate::http_messages::{
+    errors::{ParseError, snippet},
+    limits::ParseLimits,
+};
+
 pub trait Serialize {
+    /// write the message into the writer, the message is not consumed
+    fn serialize_into<W: Write>(&self, writer: &mut W) -> std::io::Result<()>;
+
     fn serialize(self) -> Vec<u8>
     where
-        Self: Sized;
+        Self: Sized,
+    {
+        let mut result = vec![];
+        // writing into a vector never fails
+        self.serialize_into(&mut result).unwrap();
+        result
+    }
 }
 
 pub trait Deserialize {
-    fn desrialize(buffer: Vec<u8>) -> Result<Self, String>
+    /// read exactly one message from the reader, the data after the message is left in the reader
+    fn deserialize_with_limits<R: BufRead>(
+        reader: &mut R,
+        limits: &ParseLimits,
+    ) -> Result<Self, ParseError>
     where
         Self: Sized;
+
+    fn deserialize_from<R: BufRead>(reader: &mut R) -> Result<Self, ParseError>
+    where
+        Self: Sized,
+    {
+        Self::deserialize_with_limits(reader, &ParseLimits::default())
+    }
+
+    /// the buffer must contain exactly one message
+    fn desrialize(buffer: Vec<u8>) -> Result<Self, ParseError>
+    where
+        Self: Sized,
+    {
+        let mut reader = buffer.as_slice();
+        let message = Self::deserialize_from(&mut reader)?;
+
+        if !reader.is_empty() {
+            return Err(ParseError::FramingMismatch {
+                offset: buffer.len() - reader.len(),
+                snippet: snippet(reader),
+            });
+        }
+
+        Ok(message)
+    }
 }
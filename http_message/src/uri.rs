@@ -0,0 +1,572 @@
+pub mod errors {
+    use std::{error::Error, fmt::Display};
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum UriError {
+        Empty,
+        /// character which is not allowed in the given part of the target, with its byte position
+        InvalidCharacter(usize, char),
+        /// `%` which is not followed by two hex digits, with its byte position
+        InvalidPercentEncoding(usize),
+        /// decoded bytes are not a valid utf8 string
+        InvalidUtf8(String),
+        Fragment,
+        InvalidScheme(String),
+        InvalidAuthority(String),
+        InvalidPort(String),
+        /// authority-form must always contain a port (RFC 9112 3.2.3)
+        MissingPort(String),
+    }
+
+    impl Display for UriError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Self::Empty => write!(f, "the request target is empty"),
+                Self::InvalidCharacter(position, char) => {
+                    write!(f, "invalid character {:?} at position {}", char, position)
+                }
+                Self::InvalidPercentEncoding(position) => write!(
+                    f,
+                    "'%' at position {} is not followed by two hex digits",
+                    position
+                ),
+                Self::InvalidUtf8(value) => {
+                    write!(f, "percent decoded value {:?} is not valid utf8", value)
+                }
+                Self::Fragment => write!(f, "the request target must not contain a fragment"),
+                Self::InvalidScheme(scheme) => write!(f, "invalid scheme {:?}", scheme),
+                Self::InvalidAuthority(authority) => {
+                    write!(f, "invalid authority {:?}", authority)
+                }
+                Self::InvalidPort(port) => write!(f, "invalid port {:?}", port),
+                Self::MissingPort(authority) => {
+                    write!(
+                        f,
+                        "authority-form target {:?} must contain a port",
+                        authority
+                    )
+                }
+            }
+        }
+    }
+
+    impl Error for UriError {}
+}
+
+/// Percent encoding as described in RFC 3986 2.1
+pub mod percent {
+    use super::errors::UriError;
+
+    pub fn is_unreserved(byte: u8) -> bool {
+        byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~')
+    }
+
+    pub fn is_sub_delim(byte: u8) -> bool {
+        matches!(
+            byte,
+            b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+' | b',' | b';' | b'='
+        )
+    }
+
+    /// characters allowed inside of a path segment
+    pub fn is_pchar(byte: u8) -> bool {
+        is_unreserved(byte) || is_sub_delim(byte) || matches!(byte, b':' | b'@')
+    }
+
+    /// characters allowed inside of a query string
+    pub fn is_query_char(byte: u8) -> bool {
+        is_pchar(byte) || matches!(byte, b'/' | b'?')
+    }
+
+    /// characters allowed inside of a query key or value, `&` and `=` are delimiters there and `+` is often read as a space
+    pub fn is_query_component_char(byte: u8) -> bool {
+        is_query_char(byte) && !matches!(byte, b'&' | b'=' | b'+')
+    }
+
+    fn hex_value(byte: u8) -> Option<u8> {
+        match byte {
+            b'0'..=b'9' => Some(byte - b'0'),
+            b'a'..=b'f' => Some(byte - b'a' + 10),
+            b'A'..=b'F' => Some(byte - b'A' + 10),
+            _ => None,
+        }
+    }
+
+    /// encode every byte for which `allowed` returns false
+    pub fn encode(input: &str, allowed: fn(u8) -> bool) -> String {
+        const HEX: &[u8; 16] = b"0123456789ABCDEF";
+
+        let mut result = String::with_capacity(input.len());
+        for &byte in input.as_bytes() {
+            if allowed(byte) {
+                result.push(byte as char);
+            } else {
+                result.push('%');
+                result.push(HEX[(byte >> 4) as usize] as char);
+                result.push(HEX[(byte & 0x0F) as usize] as char);
+            }
+        }
+        result
+    }
+
+    pub fn decode_bytes(input: &str) -> Result<Vec<u8>, UriError> {
+        let bytes = input.as_bytes();
+        let mut result = Vec::with_capacity(bytes.len());
+
+        let mut index = 0;
+        while index < bytes.len() {
+            if bytes[index] == b'%' {
+                let high = bytes.get(index + 1).and_then(|&b| hex_value(b));
+                let low = bytes.get(index + 2).and_then(|&b| hex_value(b));
+
+                match (high, low) {
+                    (Some(high), Some(low)) => result.push(high << 4 | low),
+                    _ => return Err(UriError::InvalidPercentEncoding(index)),
+                }
+                index += 3;
+            } else {
+                result.push(bytes[index]);
+                index += 1;
+            }
+        }
+
+        Ok(result)
+    }
+
+    pub fn decode(input: &str) -> Result<String, UriError> {
+        String::from_utf8(decode_bytes(input)?)
+            .map_err(|_| UriError::InvalidUtf8(input.to_string()))
+    }
+
+    /// Check that `input` consists only of `allowed` characters and valid percent triplets.
+    ///
+    /// Returns the normalized version: hex digits are uppercased and encoded unreserved characters are decoded (RFC 3986 6.2.2)
+    pub fn validate(
+        input: &str,
+        offset: usize,
+        allowed: fn(u8) -> bool,
+    ) -> Result<String, UriError> {
+        let bytes = input.as_bytes();
+        let mut result = String::with_capacity(input.len());
+
+        let mut index = 0;
+        while index < bytes.len() {
+            let byte = bytes[index];
+            if byte == b'%' {
+                let high = bytes.get(index + 1).and_then(|&b| hex_value(b));
+                let low = bytes.get(index + 2).and_then(|&b| hex_value(b));
+
+                let decoded = match (high, low) {
+                    (Some(high), Some(low)) => high << 4 | low,
+                    _ => return Err(UriError::InvalidPercentEncoding(offset + index)),
+                };
+
+                if is_unreserved(decoded) {
+                    result.push(decoded as char);
+                } else {
+                    result.push('%');
+                    result.push(bytes[index + 1].to_ascii_uppercase() as char);
+                    result.push(bytes[index + 2].to_ascii_uppercase() as char);
+                }
+                index += 3;
+            } else if allowed(byte) {
+                result.push(byte as char);
+                index += 1;
+            } else {
+                // the input is a valid str, so the char boundary is here
+                let char = input[index..].chars().next().unwrap();
+                return Err(UriError::InvalidCharacter(offset + index, char));
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+pub mod query {
+    use super::{errors::UriError, percent};
+
+    /// Query string together with its decoded pairs in their order. A key without `=` has no value.
+    ///
+    /// The query is sent as it was given, so the encoding chosen by the server (like `+`) is not changed
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    pub struct Query {
+        /// the encoded query
+        raw: String,
+        pairs: Vec<(String, Option<String>)>,
+    }
+
+    impl Query {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn parse(query: &str) -> Result<Self, UriError> {
+            let pairs = query
+                .split('&')
+                .filter(|pair| !pair.is_empty())
+                .map(|pair| match pair.split_once('=') {
+                    Some((key, value)) => {
+                        Ok((percent::decode(key)?, Some(percent::decode(value)?)))
+                    }
+                    None => Ok((percent::decode(pair)?, None)),
+                })
+                .collect::<Result<Vec<_>, UriError>>()?;
+
+            Ok(Self {
+                raw: query.to_string(),
+                pairs,
+            })
+        }
+
+        /// the pair is encoded and added to the end of the query
+        pub fn append(&mut self, key: &str, value: &str) -> &mut Self {
+            if !self.raw.is_empty() {
+                self.raw.push('&');
+            }
+            self.raw.push_str(&format!(
+                "{}={}",
+                percent::encode(key, percent::is_query_component_char),
+                percent::encode(value, percent::is_query_component_char)
+            ));
+
+            self.pairs.push((key.to_string(), Some(value.to_string())));
+            self
+        }
+
+        pub fn pairs(&self) -> &[(String, Option<String>)] {
+            &self.pairs
+        }
+
+        /// first value of the key
+        pub fn get(&self, key: &str) -> Option<&str> {
+            self.pairs
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value.as_deref().unwrap_or(""))
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.pairs.is_empty()
+        }
+    }
+
+    impl std::fmt::Display for Query {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}", self.raw)
+        }
+    }
+}
+
+pub mod authority {
+    use super::{errors::UriError, percent};
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    pub struct Authority {
+        pub host: String,
+        pub port: Option<u16>,
+    }
+
+    impl Authority {
+        pub fn parse(authority: &str) -> Result<Self, UriError> {
+            if authority.is_empty() || authority.contains('@') {
+                // userinfo is deprecated for http uris (RFC 9110 4.2.4)
+                return Err(UriError::InvalidAuthority(authority.to_string()));
+            }
+
+            let (host, port) = if authority.starts_with('[') {
+                let end = authority
+                    .find(']')
+                    .ok_or_else(|| UriError::InvalidAuthority(authority.to_string()))?;
+                let host = &authority[..=end];
+
+                if !host[1..end]
+                    .bytes()
+                    .all(|b| b.is_ascii_hexdigit() || b == b':' || b == b'.')
+                {
+                    return Err(UriError::InvalidAuthority(authority.to_string()));
+                }
+
+                match &authority[end + 1..] {
+                    "" => (host, None),
+                    rest => match rest.strip_prefix(':') {
+                        Some(port) => (host, Some(port)),
+                        None => return Err(UriError::InvalidAuthority(authority.to_string())),
+                    },
+                }
+            } else {
+                match authority.rsplit_once(':') {
+                    Some((host, port)) => (host, Some(port)),
+                    None => (authority, None),
+                }
+            };
+
+            if host.is_empty() {
+                return Err(UriError::InvalidAuthority(authority.to_string()));
+            }
+
+            if !host.starts_with('[') {
+                percent::validate(host, 0, |b| {
+                    percent::is_unreserved(b) || percent::is_sub_delim(b)
+                })
+                .map_err(|_| UriError::InvalidAuthority(authority.to_string()))?;
+            }
+
+            let port = match port {
+                // empty port is allowed by RFC 3986 3.2.3
+                None | Some("") => None,
+                Some(port) => Some(
+                    port.parse::<u16>()
+                        .map_err(|_| UriError::InvalidPort(port.to_string()))?,
+                ),
+            };
+
+            Ok(Self {
+                host: host.to_ascii_lowercase(),
+                port,
+            })
+        }
+    }
+
+    impl std::fmt::Display for Authority {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self.port {
+                Some(port) => write!(f, "{}:{}", self.host, port),
+                None => write!(f, "{}", self.host),
+            }
+        }
+    }
+}
+
+/// All forms of the request target from RFC 9112 3.2
+pub mod target {
+    use super::{authority::Authority, errors::UriError};
+    use crate::http_messages::path::Path;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum RequestTarget {
+        /// `/where?q=now`, used for direct requests to the origin server
+        Origin(Path),
+        /// `http://www.example.org/pub/WWW/TheProject.html`, used for requests to a proxy
+        Absolute {
+            scheme: String,
+            authority: Authority,
+            path: Path,
+        },
+        /// `www.example.com:80`, used only for CONNECT
+        Authority(Authority),
+        /// `*`, used only for server wide OPTIONS
+        Asterisk,
+    }
+
+    impl RequestTarget {
+        pub fn parse(target: &str) -> Result<Self, UriError> {
+            if target.is_empty() {
+                return Err(UriError::Empty);
+            }
+
+            if target == "*" {
+                return Ok(Self::Asterisk);
+            }
+
+            if target.starts_with('/') {
+                return Ok(Self::Origin(Path::new(target.to_string())?));
+            }
+
+            if let Some((scheme, rest)) = target.split_once("://") {
+                let mut chars = scheme.chars();
+                if !chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+                    || !chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+                {
+                    return Err(UriError::InvalidScheme(scheme.to_string()));
+                }
+
+                let path_start = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+                let authority = Authority::parse(&rest[..path_start])?;
+
+                let path = match &rest[path_start..] {
+                    "" => Path::default(),
+                    // absolute uri with an empty path, but with a query
+                    path if path.starts_with('?') => Path::new(format!("/{}", path))?,
+                    path => Path::new(path.to_string())?,
+                };
+
+                return Ok(Self::Absolute {
+                    scheme: scheme.to_ascii_lowercase(),
+                    authority,
+                    path,
+                });
+            }
+
+            let authority = Authority::parse(target)?;
+            if authority.port.is_none() {
+                return Err(UriError::MissingPort(target.to_string()));
+            }
+            Ok(Self::Authority(authority))
+        }
+
+        /// path of the target, if the target has one
+        pub fn path(&self) -> Option<&Path> {
+            match self {
+                Self::Origin(path) | Self::Absolute { path, .. } => Some(path),
+                Self::Authority(_) | Self::Asterisk => None,
+            }
+        }
+    }
+
+    impl From<Path> for RequestTarget {
+        fn from(value: Path) -> Self {
+            Self::Origin(value)
+        }
+    }
+
+    impl Default for RequestTarget {
+        fn default() -> Self {
+            Self::Origin(Path::default())
+        }
+    }
+
+    impl std::str::FromStr for RequestTarget {
+        type Err = UriError;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            Self::parse(s)
+        }
+    }
+
+    impl std::fmt::Display for RequestTarget {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Self::Origin(path) => write!(f, "{}", path),
+                Self::Absolute {
+                    scheme,
+                    authority,
+                    path,
+                } => write!(f, "{}://{}{}", scheme, authority, path),
+                Self::Authority(authority) => write!(f, "{}", authority),
+                Self::Asterisk => write!(f, "*"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{errors::UriError, percent, query::Query, target::RequestTarget};
+    use crate::http_messages::path::Path;
+
+    #[test]
+    fn test_percent_roundtrip() {
+        let encoded = percent::encode("data file/ä?.bin", percent::is_pchar);
+        assert_eq!(encoded, "data%20file%2F%C3%A4%3F.bin");
+        assert_eq!(percent::decode(&encoded).unwrap(), "data file/ä?.bin");
+
+        assert_eq!(
+            percent::decode("abc%2"),
+            Err(UriError::InvalidPercentEncoding(3))
+        );
+    }
+
+    #[test]
+    fn test_origin_form() {
+        let path = Path::new("/files/data.bin?token=x".to_string()).unwrap();
+
+        assert_eq!(path.path, "/files/data.bin");
+        assert_eq!(path.query.as_ref().unwrap().get("token"), Some("x"));
+        assert_eq!(path.to_string(), "/files/data.bin?token=x");
+    }
+
+    #[test]
+    fn test_path_normalization() {
+        let path = Path::new("/a/./b/../../c/%7euser/%2f".to_string()).unwrap();
+        assert_eq!(path.path, "/c/~user/%2F");
+
+        let path = Path::new("/../../x/.".to_string()).unwrap();
+        assert_eq!(path.path, "/x/");
+    }
+
+    #[test]
+    fn test_invalid_paths() {
+        assert_eq!(Path::new("".to_string()), Err(UriError::Empty));
+        assert_eq!(
+            Path::new("files".to_string()),
+            Err(UriError::InvalidCharacter(0, 'f'))
+        );
+        assert_eq!(
+            Path::new("/a b".to_string()),
+            Err(UriError::InvalidCharacter(2, ' '))
+        );
+        assert_eq!(
+            Path::new("/a%zz".to_string()),
+            Err(UriError::InvalidPercentEncoding(2))
+        );
+        assert_eq!(Path::new("/a#top".to_string()), Err(UriError::Fragment));
+    }
+
+    #[test]
+    fn test_query_building() {
+        let mut query = Query::new();
+        query.append("name", "a b&c").append("empty", "");
+
+        let path = Path::new("/search".to_string()).unwrap().with_query(query);
+        assert_eq!(path.to_string(), "/search?name=a%20b%26c&empty=");
+
+        let parsed = Path::new(path.to_string()).unwrap();
+        assert_eq!(parsed.query.unwrap().get("name"), Some("a b&c"));
+
+        // the received query is sent back as it was, even if it could be encoded differently
+        for raw in ["/search?q=a+b&x=%7e", "/search?flag&&q=%2F"] {
+            assert_eq!(Path::new(raw.to_string()).unwrap().to_string(), raw);
+        }
+    }
+
+    #[test]
+    fn test_absolute_form() {
+        let target =
+            RequestTarget::parse("HTTP://Example.com:8080/files/data.bin?token=x").unwrap();
+
+        match &target {
+            RequestTarget::Absolute {
+                scheme,
+                authority,
+                path,
+            } => {
+                assert_eq!(scheme, "http");
+                assert_eq!(authority.host, "example.com");
+                assert_eq!(authority.port, Some(8080));
+                assert_eq!(path.path, "/files/data.bin");
+            }
+            _ => panic!("wrong target form: {:?}", target),
+        }
+        assert_eq!(
+            target.to_string(),
+            "http://example.com:8080/files/data.bin?token=x"
+        );
+
+        assert_eq!(
+            RequestTarget::parse("http://[::1]").unwrap().to_string(),
+            "http://[::1]/"
+        );
+        assert!(matches!(
+            RequestTarget::parse("1http://host/"),
+            Err(UriError::InvalidScheme(_))
+        ));
+    }
+
+    #[test]
+    fn test_authority_form() {
+        let target = RequestTarget::parse("127.0.0.1:8080").unwrap();
+        assert_eq!(target.to_string(), "127.0.0.1:8080");
+        assert!(target.path().is_none());
+
+        assert!(matches!(
+            RequestTarget::parse("example.com"),
+            Err(UriError::MissingPort(_))
+        ));
+        assert!(matches!(
+            RequestTarget::parse("example.com:http"),
+            Err(UriError::InvalidPort(_))
+        ));
+        assert_eq!(RequestTarget::parse("*").unwrap(), RequestTarget::Asterisk);
+    }
+}
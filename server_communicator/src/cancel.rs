@@ -0,0 +1,130 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::{RequestId, transport::Aborter};
+
+#[derive(Default)]
+struct CancelState {
+    /// sent requests, which were not answered yet
+    pending: HashSet<RequestId>,
+    cancelled: HashSet<RequestId>,
+    /// connections of the requests in progress
+    in_flight: HashMap<RequestId, Aborter>,
+    /// the communicator was shut down, no requests are accepted anymore
+    closed: bool,
+}
+
+/// Cancelled requests, shared by the request senders and the workers
+#[derive(Clone, Default)]
+pub(crate) struct Cancellation(Arc<Mutex<CancelState>>);
+
+impl std::fmt::Debug for Cancellation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cancellation")
+            .field("pending", &self.lock().pending.len())
+            .finish()
+    }
+}
+
+/// The request is in progress on a connection until it is dropped
+pub(crate) struct InProgress<'a> {
+    cancellation: &'a Cancellation,
+    id: RequestId,
+}
+
+impl InProgress<'_> {
+    /// The request goes on on a new connection. Returns false if it was cancelled in the meantime
+    pub fn restart(&self, aborter: Option<Aborter>) -> bool {
+        let mut state = self.cancellation.lock();
+        if state.cancelled.contains(&self.id) {
+            return false;
+        }
+
+        match aborter {
+            Some(aborter) => state.in_flight.insert(self.id, aborter),
+            None => state.in_flight.remove(&self.id),
+        };
+        true
+    }
+}
+
+impl Drop for InProgress<'_> {
+    fn drop(&mut self) {
+        self.cancellation.lock().in_flight.remove(&self.id);
+    }
+}
+
+impl Cancellation {
+    fn lock(&self) -> MutexGuard<'_, CancelState> {
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Returns false if the communicator does not accept requests anymore
+    pub fn sent(&self, id: RequestId) -> bool {
+        let mut state = self.lock();
+        if state.closed {
+            return false;
+        }
+
+        state.pending.insert(id);
+        true
+    }
+
+    /// No requests are accepted anymore. If `abort`, all sent requests are cancelled and their connections are closed
+    pub fn close(&self, abort: bool) {
+        let mut state = self.lock();
+        state.closed = true;
+        if !abort {
+            return;
+        }
+
+        let pending = state.pending.iter().copied().collect::<Vec<_>>();
+        state.cancelled.extend(pending);
+        state.in_flight.drain().for_each(|(_, abort)| abort());
+    }
+
+    /// the request was answered, so it can not be cancelled anymore
+    pub fn finish(&self, id: RequestId) {
+        let mut state = self.lock();
+        state.pending.remove(&id);
+        state.cancelled.remove(&id);
+        state.in_flight.remove(&id);
+    }
+
+    /// Returns false if the request was already answered
+    pub fn cancel(&self, id: RequestId) -> bool {
+        let mut state = self.lock();
+        if !state.pending.contains(&id) {
+            return false;
+        }
+
+        state.cancelled.insert(id);
+        if let Some(abort) = state.in_flight.remove(&id) {
+            abort();
+        }
+        true
+    }
+
+    pub fn is_cancelled(&self, id: RequestId) -> bool {
+        self.lock().cancelled.contains(&id)
+    }
+
+    /// Registers the connection, which is used for the request, so the cancellation can abort it.
+    /// None if the request was already cancelled
+    pub fn start(&self, id: RequestId, aborter: Option<Aborter>) -> Option<InProgress<'_>> {
+        let mut state = self.lock();
+        if state.cancelled.contains(&id) {
+            return None;
+        }
+
+        if let Some(aborter) = aborter {
+            state.in_flight.insert(id, aborter);
+        }
+        Some(InProgress {
+            cancellation: self,
+            id,
+        })
+    }
+}
@@ -0,0 +1,177 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+    mpsc::{Receiver, Sender, channel},
+};
+
+use crate::{
+    HttpRequest, HttpResponse, ServerCommunicatorError,
+    cancel::Cancellation,
+    priority::{Priorities, Priority},
+    stream::{BodyEvent, Streams},
+};
+
+/// Identifier of the submitted request, the response to this request comes back with the same id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RequestId(u64);
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// request together with its id, as it is received by the communicator
+pub type IdentifiedRequest = (RequestId, HttpRequest);
+
+/// response or the error, which happened while handling the request, together with the id of the request it answers
+pub type IdentifiedResponse = (RequestId, Result<HttpResponse, ServerCommunicatorError>);
+
+/// Sending side of the request channel, every sent request gets a unique id. Clones share the same id sequence
+#[derive(Debug, Clone)]
+pub struct RequestSender {
+    sender: Sender<IdentifiedRequest>,
+    next_id: Arc<AtomicU64>,
+    streams: Streams,
+    cancellation: Cancellation,
+    priorities: Priorities,
+    /// priority of the requests sent by this sender
+    priority: Priority,
+}
+
+impl RequestSender {
+    fn next_id(&self) -> RequestId {
+        RequestId(self.next_id.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub(crate) fn streams(&self) -> Streams {
+        self.streams.clone()
+    }
+
+    pub(crate) fn cancellation(&self) -> Cancellation {
+        self.cancellation.clone()
+    }
+
+    pub(crate) fn priorities(&self) -> Priorities {
+        self.priorities.clone()
+    }
+
+    /// Sender of the same channel, which sends the requests with the given priority
+    pub fn with_priority(&self, priority: Priority) -> Self {
+        Self {
+            priority,
+            ..self.clone()
+        }
+    }
+
+    /// Changes the priority of the request, which is still queued. Returns false if a worker has already taken it
+    pub fn set_priority(&self, id: RequestId, priority: Priority) -> bool {
+        self.priorities.change(id, priority)
+    }
+
+    /// Cancels the request: a queued request is not sent and frees its place in the bounded queue at once,
+    /// the connection of a request in progress is closed.
+    /// It is answered with [`ServerCommunicatorError::Cancelled`], a queued one when a worker takes it.
+    ///
+    /// Returns false if the request was already answered (or was never sent)
+    pub fn cancel(&self, id: RequestId) -> bool {
+        if !self.cancellation.cancel(id) {
+            return false;
+        }
+
+        self.priorities.remove(id);
+        true
+    }
+
+    /// Blocks while the bounded queue is full. The error means, that the communicator was stopped
+    pub fn send(&self, request: HttpRequest) -> Result<RequestId, ServerCommunicatorError> {
+        let id = self.next_id();
+        self.send_as(id, request)?;
+        Ok(id)
+    }
+
+    fn send_as(&self, id: RequestId, request: HttpRequest) -> Result<(), ServerCommunicatorError> {
+        if !self.cancellation.sent(id) {
+            return Err(ServerCommunicatorError::Terminate);
+        }
+        if !self.priorities.queued(id, self.priority) {
+            self.cancellation.finish(id);
+            return Err(ServerCommunicatorError::Terminate);
+        }
+        self.enqueue(id, request)
+    }
+
+    /// puts the request, which already has its place in the queue, into the channel
+    fn enqueue(&self, id: RequestId, request: HttpRequest) -> Result<(), ServerCommunicatorError> {
+        match self.sender.send((id, request)) {
+            Ok(()) => {
+                self.priorities.arrived();
+                Ok(())
+            }
+            Err(err) => {
+                self.cancellation.finish(id);
+                self.priorities.remove(id);
+                Err(err.into())
+            }
+        }
+    }
+
+    /// Like [`RequestSender::send`], but the parts of the body are sent through the returned receiver as soon as they arrive,
+    /// so the data is available even if the connection fails in the middle of the body.
+    /// The body is not copied into the response, so its body is empty
+    pub fn send_streaming(
+        &self,
+        request: HttpRequest,
+    ) -> Result<(RequestId, Receiver<BodyEvent>), ServerCommunicatorError> {
+        let id = self.next_id();
+        let events = self.streams.subscribe(id);
+
+        // the events must be subscribed before a worker can take the request
+        self.send_as(id, request)
+            .inspect_err(|_| self.streams.unsubscribe(id))?;
+        Ok((id, events))
+    }
+
+    /// Like [`RequestSender::send`], but fails with [`ServerCommunicatorError::QueueFull`] instead of blocking
+    pub fn try_send(&self, request: HttpRequest) -> Result<RequestId, ServerCommunicatorError> {
+        let id = self.next_id();
+        if !self.cancellation.sent(id) {
+            return Err(ServerCommunicatorError::Terminate);
+        }
+        if !self.priorities.try_queued(id, self.priority) {
+            self.cancellation.finish(id);
+            return Err(ServerCommunicatorError::QueueFull);
+        }
+        self.enqueue(id, request)?;
+
+        Ok(id)
+    }
+}
+
+fn request_sender(sender: Sender<IdentifiedRequest>, priorities: Priorities) -> RequestSender {
+    RequestSender {
+        sender,
+        next_id: Arc::new(AtomicU64::new(0)),
+        streams: Streams::default(),
+        cancellation: Cancellation::default(),
+        priorities,
+        priority: Priority::default(),
+    }
+}
+
+/// Creates the request channel, the receiving side gets requests with their ids
+pub fn request_channel() -> (RequestSender, Receiver<IdentifiedRequest>) {
+    let (sender, receiver) = channel();
+
+    (request_sender(sender, Priorities::default()), receiver)
+}
+
+/// Creates the request channel, which keeps at most `capacity` requests, until the workers take them
+pub fn bounded_request_channel(capacity: usize) -> (RequestSender, Receiver<IdentifiedRequest>) {
+    let (sender, receiver) = channel();
+
+    (
+        request_sender(sender, Priorities::bounded(capacity)),
+        receiver,
+    )
+}
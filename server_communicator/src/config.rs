@@ -0,0 +1,73 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use http_message::http_messages::limits::ParseLimits;
+
+use crate::{
+    handle::ShutdownPolicy,
+    limit::RateLimit,
+    proxy::ProxyConfig,
+    redirect::RedirectPolicy,
+    retry::{RetryPolicy, TimeoutConfig},
+    throttle::Throttle,
+    transport::{TcpTransport, Transport},
+};
+
+/// Configuration of the communicator, default values are suitable for the task server
+#[derive(Debug, Clone)]
+pub struct CommunicatorConfig {
+    /// limits for the responses, a response which exceeds them is reported as a serialize error
+    pub limits: ParseLimits,
+    /// keep-alive connections which are idle for longer are closed
+    pub idle_timeout: Duration,
+    /// amount of threads which handle requests in parallel. With more than one worker responses can come in a different order than requests
+    pub workers: usize,
+    /// maximum amount of open connections to one host, workers wait for a free connection
+    pub max_connections_per_host: usize,
+    /// amount of queued requests to one host, which are sent on one connection without waiting for the responses.
+    /// 1 disables pipelining, it is also disabled while recording
+    pub pipeline_depth: usize,
+    pub rate_limit: RateLimit,
+    /// read-side bandwidth limits, a clone of the throttle changes them at runtime
+    pub throttle: Throttle,
+    /// maximum amount of requests, which are handled at the same time by all workers
+    pub max_in_flight: Option<usize>,
+    /// maximum amount of queued requests, sending blocks (or fails with `try_send`) when the queue is full. `None` is unbounded
+    pub queue_capacity: Option<usize>,
+    /// a queued request gains one priority level for each interval it waits, so low priorities are not starved. `None` disables it
+    pub priority_aging: Option<Duration>,
+    pub timeouts: TimeoutConfig,
+    /// applied to each request separately
+    pub retry: RetryPolicy,
+    pub redirect: RedirectPolicy,
+    pub shutdown_policy: ShutdownPolicy,
+    /// the way connections are established, TCP by default
+    pub transport: Arc<dyn Transport>,
+    /// the connections (of the transport) go through the proxy
+    pub proxy: Option<ProxyConfig>,
+    /// all requests and responses are written into the file, it can be replayed with [`crate::ReplayTransport`]
+    pub record: Option<PathBuf>,
+}
+
+impl Default for CommunicatorConfig {
+    fn default() -> Self {
+        Self {
+            limits: ParseLimits::default(),
+            idle_timeout: Duration::from_secs(30),
+            workers: 1,
+            max_connections_per_host: 4,
+            pipeline_depth: 1,
+            rate_limit: RateLimit::default(),
+            throttle: Throttle::default(),
+            max_in_flight: None,
+            queue_capacity: None,
+            priority_aging: Some(Duration::from_secs(1)),
+            timeouts: TimeoutConfig::default(),
+            retry: RetryPolicy::default(),
+            redirect: RedirectPolicy::default(),
+            shutdown_policy: ShutdownPolicy::default(),
+            transport: Arc::new(TcpTransport::default()),
+            proxy: None,
+            record: None,
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    ops::Range,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::transport::{Aborter, Stream, Transport};
+
+/// Probabilities of the injected faults, all of them are from 0 to 1.
+///
+/// The same seed gives the same faults, as long as connections are established in the same order
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaultProfile {
+    pub seed: u64,
+    /// establishing of a connection fails with `ConnectionRefused`
+    pub fail_connect: f64,
+    /// the connection is reset at a random point of the received data
+    pub drop_connection: f64,
+    /// the server closes the connection at a random point of the received data, so the body is shorter than announced
+    pub truncate: f64,
+    /// amount of received bytes after which the connection is dropped or truncated
+    pub cut_after: Range<usize>,
+    /// a read is delayed by a random duration up to `max_delay`
+    pub delay: f64,
+    pub max_delay: Duration,
+    /// one byte of a read is changed
+    pub corrupt: f64,
+    /// the response is held back until a response on a later connection is received, or `max_delay` passes
+    pub reorder: f64,
+}
+
+impl Default for FaultProfile {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            fail_connect: 0.0,
+            drop_connection: 0.0,
+            truncate: 0.0,
+            cut_after: 0..1024,
+            delay: 0.0,
+            max_delay: Duration::from_millis(100),
+            corrupt: 0.0,
+            reorder: 0.0,
+        }
+    }
+}
+
+/// SplitMix64, small and good enough to make faults repeatable
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn fraction(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1_u64 << 53) as f64
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        self.fraction() < probability
+    }
+
+    fn in_range(&mut self, range: &Range<usize>) -> usize {
+        match range.end.saturating_sub(range.start) {
+            0 => range.start,
+            len => range.start + (self.next_u64() % len as u64) as usize,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cut {
+    Drop,
+    Truncate,
+}
+
+#[derive(Default)]
+struct Progress {
+    next_connection: u64,
+    /// the latest connection, which already received data
+    latest_received: Option<u64>,
+}
+
+impl std::fmt::Debug for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} connections", self.next_connection)
+    }
+}
+
+/// Wraps another transport and injects faults into its connections according to the profile
+#[derive(Debug)]
+pub struct FaultyTransport {
+    inner: Arc<dyn Transport>,
+    profile: FaultProfile,
+    rng: Mutex<Rng>,
+    progress: Arc<(Mutex<Progress>, Condvar)>,
+}
+
+impl FaultyTransport {
+    pub fn new(inner: Arc<dyn Transport>, profile: FaultProfile) -> Self {
+        Self {
+            inner,
+            rng: Mutex::new(Rng(profile.seed)),
+            profile,
+            progress: Default::default(),
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+impl Transport for FaultyTransport {
+    fn connect(&self, host: &str, timeout: Option<Duration>) -> std::io::Result<Box<dyn Stream>> {
+        let profile = &self.profile;
+
+        // all random decisions about the connection are made at once, so they do not depend on the timing of reads
+        let mut rng = lock(&self.rng);
+        if rng.chance(profile.fail_connect) {
+            return Err(std::io::Error::new(
+                ErrorKind::ConnectionRefused,
+                "injected connect failure",
+            ));
+        }
+
+        let cut = if rng.chance(profile.drop_connection) {
+            Some((rng.in_range(&profile.cut_after), Cut::Drop))
+        } else if rng.chance(profile.truncate) {
+            Some((rng.in_range(&profile.cut_after), Cut::Truncate))
+        } else {
+            None
+        };
+        let reorder = rng.chance(profile.reorder);
+        let stream_rng = Rng(rng.next_u64());
+        drop(rng);
+
+        let inner = self.inner.connect(host, timeout)?;
+
+        let id = {
+            let mut progress = lock(&self.progress.0);
+            progress.next_connection += 1;
+            progress.next_connection - 1
+        };
+
+        Ok(Box::new(FaultyStream {
+            inner,
+            profile: profile.clone(),
+            rng: stream_rng,
+            cut,
+            reorder,
+            received: 0,
+            id,
+            progress: self.progress.clone(),
+        }))
+    }
+}
+
+struct FaultyStream {
+    inner: Box<dyn Stream>,
+    profile: FaultProfile,
+    rng: Rng,
+    cut: Option<(usize, Cut)>,
+    /// the first data is held back until a later connection receives data
+    reorder: bool,
+    received: usize,
+    id: u64,
+    progress: Arc<(Mutex<Progress>, Condvar)>,
+}
+
+impl FaultyStream {
+    fn hold_back(&mut self) {
+        let (progress, changed) = &*self.progress;
+        let deadline = Instant::now() + self.profile.max_delay;
+
+        let mut state = lock(progress);
+        while state.latest_received.is_none_or(|latest| latest <= self.id) {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                break;
+            }
+            state = changed
+                .wait_timeout(state, timeout)
+                .unwrap_or_else(|err| err.into_inner())
+                .0;
+        }
+    }
+
+    fn mark_received(&self) {
+        let (progress, changed) = &*self.progress;
+        let mut state = lock(progress);
+        state.latest_received = state.latest_received.max(Some(self.id));
+        changed.notify_all();
+    }
+}
+
+impl Read for FaultyStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.reorder {
+            self.reorder = false;
+            self.hold_back();
+        }
+
+        if self.rng.chance(self.profile.delay) {
+            let max_delay = self.profile.max_delay.as_secs_f64();
+            std::thread::sleep(Duration::from_secs_f64(max_delay * self.rng.fraction()));
+        }
+
+        let limit = match self.cut {
+            Some((cut_after, cut)) if self.received >= cut_after => {
+                return match cut {
+                    Cut::Drop => Err(std::io::Error::new(
+                        ErrorKind::ConnectionReset,
+                        "injected connection drop",
+                    )),
+                    Cut::Truncate => Ok(0),
+                };
+            }
+            Some((cut_after, _)) => buf.len().min(cut_after - self.received),
+            None => buf.len(),
+        };
+
+        let len = self.inner.read(&mut buf[..limit])?;
+        self.received += len;
+
+        if len > 0 {
+            if self.rng.chance(self.profile.corrupt) {
+                let index = self.rng.in_range(&(0..len));
+                buf[index] = !buf[index];
+            }
+            self.mark_received();
+        }
+
+        Ok(len)
+    }
+}
+
+impl Write for FaultyStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Stream for FaultyStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.inner.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.inner.set_write_timeout(timeout)
+    }
+
+    fn aborter(&self) -> Option<Aborter> {
+        self.inner.aborter()
+    }
+
+    fn is_closed(&self) -> bool {
+        self.inner.is_closed()
+            || self
+                .cut
+                .is_some_and(|(cut_after, _)| self.received >= cut_after)
+    }
+}
@@ -0,0 +1,111 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread::JoinHandle,
+};
+
+use crate::{metrics::Metrics, throttle::Throttle, worker::Shared};
+
+/// What happens with the requests which were sent before the shutdown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShutdownPolicy {
+    /// all sent requests are handled before the workers stop
+    #[default]
+    Drain,
+    /// queued requests are answered with [`crate::ServerCommunicatorError::Terminate`],
+    /// connections of the requests in progress are closed and they are answered with [`crate::ServerCommunicatorError::Cancelled`]
+    Cancel,
+}
+
+/// Statistics of the communicator since the start
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CommunicatorStats {
+    /// requests answered with a response
+    pub completed: usize,
+    /// requests answered with an error
+    pub failed: usize,
+    /// requests which were not sent because of the shutdown
+    pub cancelled: usize,
+    /// repeated attempts of all requests
+    pub retries: usize,
+}
+
+#[derive(Default)]
+pub(crate) struct StatsCounters {
+    pub completed: AtomicUsize,
+    pub failed: AtomicUsize,
+    pub cancelled: AtomicUsize,
+    pub retries: AtomicUsize,
+}
+
+impl StatsCounters {
+    pub fn snapshot(&self) -> CommunicatorStats {
+        CommunicatorStats {
+            completed: self.completed.load(Ordering::SeqCst),
+            failed: self.failed.load(Ordering::SeqCst),
+            cancelled: self.cancelled.load(Ordering::SeqCst),
+            retries: self.retries.load(Ordering::SeqCst),
+        }
+    }
+}
+
+/// Handle of the started communicator, the workers keep running if it is dropped
+pub struct CommunicatorHandle {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl CommunicatorHandle {
+    pub(crate) fn new(shared: Arc<Shared>, workers: Vec<JoinHandle<()>>) -> Self {
+        Self { shared, workers }
+    }
+
+    /// Asks the workers to stop according to the configured [`ShutdownPolicy`], does not wait for them.
+    /// Requests sent after it fail with [`crate::ServerCommunicatorError::Terminate`].
+    ///
+    /// The workers also stop by themselves, when all request senders are dropped
+    pub fn shutdown(&self) {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        self.shared
+            .cancellation
+            .close(self.shared.config.shutdown_policy == ShutdownPolicy::Cancel);
+        self.shared.priorities.close();
+    }
+
+    pub fn stats(&self) -> CommunicatorStats {
+        self.shared.stats.snapshot()
+    }
+
+    /// the bandwidth limits of the communicator can be changed with it
+    pub fn throttle(&self) -> Throttle {
+        self.shared.config.throttle.clone()
+    }
+
+    /// latencies and counters collected so far
+    pub fn metrics(&self) -> Metrics {
+        self.shared.metrics()
+    }
+
+    /// Waits until all workers stop and returns the final statistics.
+    ///
+    /// If a worker panicked, the panic payload is returned
+    pub fn join(self) -> std::thread::Result<CommunicatorStats> {
+        self.join_with_metrics().map(|(stats, _)| stats)
+    }
+
+    /// Waits until all workers stop and returns the final statistics together with the final metrics
+    pub fn join_with_metrics(self) -> std::thread::Result<(CommunicatorStats, Metrics)> {
+        let mut result = Ok(());
+        for worker in self.workers {
+            if let Err(err) = worker.join()
+                && result.is_ok()
+            {
+                result = Err(err);
+            }
+        }
+
+        result.map(|_| (self.shared.stats.snapshot(), self.shared.metrics()))
+    }
+}
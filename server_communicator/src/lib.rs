@@ -1,37 +1,109 @@
+mod cancel;
+pub mod channel;
+pub mod config;
+pub mod fault;
+pub mod handle;
+pub mod limit;
+pub mod metrics;
+pub mod mock;
+mod pipeline;
+pub mod pool;
+pub mod priority;
+pub mod proxy;
+pub mod record;
+pub mod redirect;
+pub mod retry;
+pub mod stream;
+#[cfg(test)]
+mod tests;
+pub mod throttle;
+pub mod transport;
+mod worker;
+
+pub use channel::{
+    IdentifiedRequest, IdentifiedResponse, RequestId, RequestSender, bounded_request_channel,
+    request_channel,
+};
+pub use config::CommunicatorConfig;
+pub use fault::{FaultProfile, FaultyTransport};
+pub use handle::{CommunicatorHandle, CommunicatorStats, ShutdownPolicy};
 pub use http_message::{
-    http_messages::{request::HttpRequest, response::HttpResponse},
+    http_messages::{
+        errors::ParseError,
+        header::{HeaderName, HeaderValue},
+        limits::ParseLimits,
+        request::HttpRequest,
+        response::{BodyFraming, HttpResponse},
+    },
     serialize::*,
 };
+use limit::Limiter;
+pub use limit::RateLimit;
+pub use metrics::{Histogram, Metrics};
+pub use mock::{Expectation, MockCommunicator, MockHandle};
+pub use pool::{Connection, ConnectionPool, PooledConnection};
+pub use priority::Priority;
+use priority::Scheduler;
+pub use proxy::{ProxyConfig, ProxyMode, ProxyTransport};
+pub use record::{Exchange, RecordingTransport, ReplayTransport, read_recording};
+pub use redirect::RedirectPolicy;
+pub use retry::{RetryPolicy, TimeoutConfig};
 use std::{
     error::Error,
-    io::{Read, Write},
-    net::TcpStream,
+    sync::{Arc, Mutex, atomic::AtomicBool},
+};
+pub use stream::BodyEvent;
+pub use throttle::{Bandwidth, Throttle, ThrottledTransport};
+#[cfg(unix)]
+pub use transport::UnixTransport;
+pub use transport::{
+    Aborter, ConnectFailure, IpPreference, LoopbackListener, LoopbackStream, LoopbackTransport,
+    Stream, TcpTransport, Transport,
 };
+use worker::{Shared, Worker};
 
 pub use std::sync::mpsc::{Receiver, RecvError, RecvTimeoutError, SendError, Sender, channel};
 
-/// Abstraction for communication with server. When the server will be updated to later http version, or will allow connection keep alive header. The Comunicator need to be improved.
-///
-/// Custom Http header X-Force-Terminate will imediately terminate the connector workflow
+/// Channels owned by the core of the application: responses are received and requests are sent through them.
+/// Every response comes with the id, which was returned when its request was sent
+pub type CommunicatorChannels = (Receiver<IdentifiedResponse>, RequestSender);
+
+/// Abstraction for communication with server. Requests are handled by a pool of workers, connections are kept alive and reused for the next requests to the same host, if the server allows it.
 pub struct ServerCommunicator {
-    requests: Receiver<HttpRequest>,
-    respons: Sender<HttpResponse>,
+    shared: Arc<Shared>,
+    respons: Sender<IdentifiedResponse>,
 }
 
 #[derive(Debug)]
 pub enum ServerCommunicatorError {
     NoHostNameinTheHeader,
     TcpError(std::io::Error),
-    SerializeError(String),
+    /// the proxy could not be reached or refused the request
+    ProxyError(std::io::Error),
+    SerializeError(ParseError),
+    /// the response was parsed, but its content is not what the client expected
+    InvalidResponse(String),
     ChannelError(String),
     TimeOutError(String),
+    /// the request was cancelled, because the communicator was shut down
     Terminate,
+    /// the request was cancelled with [`RequestSender::cancel`]
+    Cancelled,
+    /// the bounded request queue is full
+    QueueFull,
+    /// the redirects lead back to an already requested location
+    RedirectLoop(String),
+    /// the request was redirected more times than the policy allows
+    TooManyRedirects(usize),
 }
 
 impl Error for ServerCommunicatorError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
-        //TODO
-        None
+        match self {
+            Self::TcpError(err) | Self::ProxyError(err) => Some(err),
+            Self::SerializeError(err) => Some(err),
+            _ => None,
+        }
     }
 
     fn description(&self) -> &str {
@@ -45,6 +117,9 @@ impl Error for ServerCommunicatorError {
 
 impl From<std::io::Error> for ServerCommunicatorError {
     fn from(value: std::io::Error) -> Self {
+        if proxy::is_proxy_error(&value) {
+            return Self::ProxyError(value);
+        }
         Self::TcpError(value)
     }
 }
@@ -55,6 +130,18 @@ impl<T> From<SendError<T>> for ServerCommunicatorError {
     }
 }
 
+impl From<ParseError> for ServerCommunicatorError {
+    fn from(value: ParseError) -> Self {
+        match value {
+            // failures of the socket are not parsing errors
+            ParseError::Io { kind, snippet, .. } => {
+                Self::TcpError(std::io::Error::new(kind, snippet))
+            }
+            value => Self::SerializeError(value),
+        }
+    }
+}
+
 impl From<RecvError> for ServerCommunicatorError {
     fn from(value: RecvError) -> Self {
         Self::ChannelError(format!("{}", value))
@@ -76,85 +163,93 @@ impl std::fmt::Display for ServerCommunicatorError {
                 "Attemp to connect to tcp socket finished with error: {}",
                 err
             ),
-            Self::SerializeError(msg) => write!(f, "Serialize error with message: {}", msg),
+            Self::ProxyError(err) => write!(f, "Proxy error: {}", err),
+            Self::SerializeError(err) => write!(f, "Serialize error: {}", err),
+            Self::InvalidResponse(msg) => write!(f, "Invalid response: {}", msg),
             Self::ChannelError(msg) => write!(f, "Channler error {}", msg),
             Self::Terminate => write!(f, "The communicator was terminated"),
+            Self::Cancelled => write!(f, "The request was cancelled"),
             Self::TimeOutError(msg) => write!(f, "Timeout in {}", msg),
+            Self::QueueFull => write!(f, "The request queue is full"),
+            Self::RedirectLoop(target) => write!(f, "Redirect loop at {}", target),
+            Self::TooManyRedirects(hops) => write!(f, "More than {} redirects", hops),
         }
     }
 }
 
 impl ServerCommunicator {
-    pub fn new() -> Result<(Self, (Receiver<HttpResponse>, Sender<HttpRequest>)), std::io::Error> {
+    pub fn new() -> Result<(Self, CommunicatorChannels), std::io::Error> {
+        Self::with_config(CommunicatorConfig::default())
+    }
+
+    pub fn with_config(
+        config: CommunicatorConfig,
+    ) -> Result<(Self, CommunicatorChannels), std::io::Error> {
         //create both chanels
-        let (tx_request, rx_request): (Sender<HttpRequest>, Receiver<HttpRequest>) = channel();
-        let (tx_response, rx_response): (Sender<HttpResponse>, Receiver<HttpResponse>) = channel();
+        let (tx_request, rx_request) = match config.queue_capacity {
+            Some(capacity) => bounded_request_channel(capacity),
+            None => request_channel(),
+        };
+        let (tx_response, rx_response): (Sender<IdentifiedResponse>, Receiver<IdentifiedResponse>) =
+            channel();
+
+        let limiter = Limiter::new(&config);
+
+        // the throttle is always in place, because its limits can be set at runtime
+        let transport: Arc<dyn Transport> = Arc::new(ThrottledTransport::new(
+            config.transport.clone(),
+            config.throttle.clone(),
+        ));
+        let transport: Arc<dyn Transport> = match &config.proxy {
+            Some(proxy) => Arc::new(ProxyTransport::new(transport, proxy.clone())),
+            None => transport,
+        };
+        // in recording mode all traffic goes through the recorder
+        let transport: Arc<dyn Transport> = match &config.record {
+            Some(path) => Arc::new(RecordingTransport::create(transport, path)?),
+            None => transport,
+        };
 
         Ok((
             Self {
-                requests: rx_request,
+                shared: Arc::new(Shared {
+                    requests: Mutex::new(Scheduler::new(
+                        rx_request,
+                        tx_request.priorities(),
+                        config.priority_aging,
+                    )),
+                    pool: ConnectionPool::new(config.idle_timeout, config.max_connections_per_host)
+                        .with_connect_timeout(config.timeouts.connect)
+                        .with_transport(transport),
+                    config,
+                    shutdown: AtomicBool::new(false),
+                    stats: Default::default(),
+                    metrics: Default::default(),
+                    limiter,
+                    streams: tx_request.streams(),
+                    cancellation: tx_request.cancellation(),
+                    priorities: tx_request.priorities(),
+                }),
                 respons: tx_response,
             },
             (rx_response, tx_request),
         ))
     }
 
-    fn workflow(&mut self, request: HttpRequest) -> Result<(), ServerCommunicatorError> {
-        // check for terminating flag
-        if request.headers.get(&"X-Force-Terminate".into()).is_some() {
-            return Err(ServerCommunicatorError::Terminate);
-        }
-
-        let addr = &request
-            .headers
-            .get(&"Host".into())
-            .ok_or_else(|| ServerCommunicatorError::NoHostNameinTheHeader)?
-            .value;
-
-        let mut stream = TcpStream::connect(addr)?;
-
-        stream.write_all(&*request.serialize())?;
-
-        let mut buffer = vec![];
-
-        let response_len = stream.read_to_end(&mut buffer)?;
-
-        if response_len == 0 {
-            Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "The lenght of read data is 0",
-            ))?
-        }
-
-        let response = HttpResponse::desrialize(buffer)
-            .map_err(|msg| ServerCommunicatorError::SerializeError(msg))?;
-
-        self.respons.send(response)?;
-
-        Ok(())
-    }
-
-    /// Incredibly simple version of communication, because server terminates connection after the request, I create new connection for each request.
-    pub fn start(mut self) {
-        std::thread::spawn(move || {
-            while let Ok(request) = self.requests.recv() {
-                match self.workflow(request) {
-                    Ok(_) => {
-                        #[cfg(debug_assertions)]
-                        println!("The value was send through the channel")
-                    }
-                    Err(err) => match err {
-                        ServerCommunicatorError::Terminate => {
-                            eprintln!("Terminatin message was received, droping channels");
-                            break;
-                        }
-                        _ => eprintln!("Error: {}", err),
-                    },
-                }
-            }
-            //unnsessesary drops, but I still like to have them there)
-            drop(self.respons);
-            drop(self.requests);
-        });
+    /// Starts the configured amount of workers, each of them takes requests from the shared channel and handles them one by one.
+    /// Connections are kept alive, if the server allows it.
+    ///
+    /// The returned handle is used to stop the workers and wait for them
+    pub fn start(self) -> CommunicatorHandle {
+        let workers = (0..self.shared.config.workers.max(1))
+            .map(|_| {
+                let worker = Worker::new(self.shared.clone(), self.respons.clone());
+                std::thread::spawn(move || worker.run())
+            })
+            .collect();
+        //unnsessesary drop, but I still like to have it there)
+        drop(self.respons);
+
+        CommunicatorHandle::new(self.shared, workers)
     }
 }
@@ -0,0 +1,183 @@
+use std::{
+    sync::{Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::CommunicatorConfig;
+
+/// Limits of the traffic to the server, `None` means no limit
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RateLimit {
+    /// attempts of requests per second, up to a second worth of requests can be sent at once
+    pub requests_per_sec: Option<f64>,
+    /// received body bytes per second, a new request waits until the bytes received before are paid off
+    pub bytes_per_sec: Option<f64>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    bucket: Mutex<Bucket>,
+}
+
+impl TokenBucket {
+    fn new(rate: f64) -> Self {
+        let capacity = rate.max(1.0);
+
+        Self {
+            rate,
+            capacity,
+            bucket: Mutex::new(Bucket {
+                tokens: capacity,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    fn refilled(&self, now: Instant) -> MutexGuard<'_, Bucket> {
+        let mut bucket = lock(&self.bucket);
+        bucket.tokens = (bucket.tokens
+            + now.duration_since(bucket.refilled).as_secs_f64() * self.rate)
+            .min(self.capacity);
+        bucket.refilled = now;
+        bucket
+    }
+
+    /// how long it takes at `now` until there are `amount` tokens
+    fn wait(&self, amount: f64, now: Instant) -> Duration {
+        let bucket = self.refilled(now);
+        Duration::from_secs_f64((amount - bucket.tokens).max(0.0) / self.rate)
+    }
+
+    /// takes `amount` tokens, if there are so many at `now`
+    fn take(&self, amount: f64, now: Instant) -> bool {
+        let mut bucket = self.refilled(now);
+        let taken = bucket.tokens >= amount;
+        if taken {
+            bucket.tokens -= amount;
+        }
+        taken
+    }
+
+    /// takes tokens without waiting, the next request waits until the debt is paid off
+    fn consume(&self, amount: f64, now: Instant) {
+        self.refilled(now).tokens -= amount;
+    }
+}
+
+struct Semaphore {
+    permits: Mutex<usize>,
+    available: Condvar,
+}
+
+/// Requests in flight, the permits are returned on drop
+pub(crate) struct InFlight<'a> {
+    semaphore: Option<&'a Semaphore>,
+    permits: usize,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        if let Some(semaphore) = self.semaphore {
+            *lock(&semaphore.permits) += self.permits;
+            semaphore.available.notify_all();
+        }
+    }
+}
+
+/// Rate and concurrency limits shared by all workers
+pub(crate) struct Limiter {
+    requests: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+    in_flight: Option<Semaphore>,
+    max_in_flight: Option<usize>,
+}
+
+impl Limiter {
+    pub fn new(config: &CommunicatorConfig) -> Self {
+        let bucket = |rate: Option<f64>| rate.filter(|rate| *rate > 0.0).map(TokenBucket::new);
+        let max_in_flight = config.max_in_flight.map(|max| max.max(1));
+
+        Self {
+            requests: bucket(config.rate_limit.requests_per_sec),
+            bytes: bucket(config.rate_limit.bytes_per_sec),
+            in_flight: max_in_flight.map(|max| Semaphore {
+                permits: Mutex::new(max),
+                available: Condvar::new(),
+            }),
+            max_in_flight,
+        }
+    }
+
+    pub fn max_in_flight(&self) -> Option<usize> {
+        self.max_in_flight
+    }
+
+    /// Blocks until `amount` more requests can be in flight, `amount` must not exceed the maximum
+    pub fn start(&self, amount: usize) -> InFlight<'_> {
+        let Some(semaphore) = &self.in_flight else {
+            return InFlight {
+                semaphore: None,
+                permits: 0,
+            };
+        };
+
+        let mut permits = lock(&semaphore.permits);
+        while *permits < amount {
+            permits = semaphore
+                .available
+                .wait(permits)
+                .unwrap_or_else(|err| err.into_inner());
+        }
+        *permits -= amount;
+
+        InFlight {
+            semaphore: Some(semaphore),
+            permits: amount,
+        }
+    }
+
+    /// Takes the token of the next attempt of a request, if it can be sent at `now`, or returns how long it has to wait
+    pub(crate) fn request_at(&self, now: Instant) -> Result<(), Duration> {
+        let wait = [(&self.requests, 1.0), (&self.bytes, 0.0)]
+            .into_iter()
+            .filter_map(|(bucket, amount)| Some(bucket.as_ref()?.wait(amount, now)))
+            .max()
+            .unwrap_or(Duration::ZERO);
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+
+        match &self.requests {
+            Some(requests) if !requests.take(1.0, now) => Err(requests.wait(1.0, now)),
+            _ => Ok(()),
+        }
+    }
+
+    /// the bytes of a response body received at `now`
+    pub(crate) fn received_at(&self, body_len: usize, now: Instant) {
+        if let Some(bytes) = &self.bytes {
+            bytes.consume(body_len as f64, now);
+        }
+    }
+
+    /// waits until the next attempt of a request can be sent
+    pub fn before_request(&self) {
+        while let Err(wait) = self.request_at(Instant::now()) {
+            std::thread::sleep(wait);
+        }
+    }
+
+    pub fn after_response(&self, body_len: usize) {
+        self.received_at(body_len, Instant::now());
+    }
+}
@@ -0,0 +1,196 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+use crate::{HttpResponse, ServerCommunicatorError};
+
+const fn millis(millis: u64) -> Duration {
+    Duration::from_millis(millis)
+}
+
+/// Histogram of durations with fixed buckets
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Histogram {
+    pub count: usize,
+    pub sum: Duration,
+    pub min: Option<Duration>,
+    pub max: Option<Duration>,
+    /// amount of values up to each of [`Histogram::BOUNDS`], the last bucket counts the values above all bounds
+    pub buckets: [usize; Histogram::BOUNDS.len() + 1],
+}
+
+impl Histogram {
+    /// upper bounds of the buckets
+    pub const BOUNDS: [Duration; 12] = [
+        millis(1),
+        millis(2),
+        millis(5),
+        millis(10),
+        millis(20),
+        millis(50),
+        millis(100),
+        millis(200),
+        millis(500),
+        millis(1000),
+        millis(2000),
+        millis(5000),
+    ];
+
+    pub fn record(&mut self, value: Duration) {
+        self.count += 1;
+        self.sum += value;
+        self.min = Some(self.min.map_or(value, |min| min.min(value)));
+        self.max = Some(self.max.map_or(value, |max| max.max(value)));
+
+        let bucket = Self::BOUNDS
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(Self::BOUNDS.len());
+        self.buckets[bucket] += 1;
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        (self.count > 0).then(|| self.sum / self.count as u32)
+    }
+
+    /// Upper estimate of the quantile (0.0..=1.0): the bound of its bucket, but not more than the maximum
+    pub fn quantile(&self, quantile: f64) -> Option<Duration> {
+        let max = self.max?;
+        let rank = ((self.count as f64 * quantile.clamp(0.0, 1.0)).ceil() as usize).max(1);
+
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some(
+                    Self::BOUNDS
+                        .get(bucket)
+                        .map_or(max, |bound| max.min(*bound)),
+                );
+            }
+        }
+
+        Some(max)
+    }
+}
+
+impl Display for Histogram {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (
+            self.mean(),
+            self.quantile(0.5),
+            self.quantile(0.99),
+            self.max,
+        ) {
+            (Some(mean), Some(p50), Some(p99), Some(max)) => write!(
+                f,
+                "{} samples, mean {:?}, p50 <= {:?}, p99 <= {:?}, max {:?}",
+                self.count, mean, p50, p99, max
+            ),
+            _ => write!(f, "no samples"),
+        }
+    }
+}
+
+/// Latencies and counters of the communicator since the start
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Metrics {
+    /// establishing of new connections
+    pub connect: Histogram,
+    /// from sending a request until the first byte of its response
+    pub first_byte: Histogram,
+    /// from taking a request from the queue until its response or error is delivered, retries included
+    pub total: Histogram,
+    /// body bytes of all received responses
+    pub bytes_received: u64,
+    /// responses which were cut short by the server
+    pub truncated: usize,
+    /// failed attempts by the kind of the error
+    pub errors: BTreeMap<String, usize>,
+    /// repeated attempts of all requests
+    pub retries: usize,
+}
+
+impl Display for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Connect: {}", self.connect)?;
+        writeln!(f, "First byte: {}", self.first_byte)?;
+        writeln!(f, "Total: {}", self.total)?;
+        writeln!(
+            f,
+            "Received {} bytes, {} truncated responses, {} retries",
+            self.bytes_received, self.truncated, self.retries
+        )?;
+        write!(f, "Errors:")?;
+        if self.errors.is_empty() {
+            write!(f, " none")?;
+        }
+        self.errors
+            .iter()
+            .try_for_each(|(kind, count)| write!(f, "\n  {}: {}", kind, count))
+    }
+}
+
+/// name of the error kind, failures of the socket are split by their io kind
+fn error_kind(err: &ServerCommunicatorError) -> String {
+    match err {
+        ServerCommunicatorError::NoHostNameinTheHeader => "no host".to_string(),
+        ServerCommunicatorError::TcpError(err) => format!("tcp: {}", err.kind()),
+        ServerCommunicatorError::ProxyError(err) => format!("proxy: {}", err.kind()),
+        ServerCommunicatorError::SerializeError(_) => "parse".to_string(),
+        ServerCommunicatorError::InvalidResponse(_) => "invalid response".to_string(),
+        ServerCommunicatorError::ChannelError(_) => "channel".to_string(),
+        ServerCommunicatorError::TimeOutError(_) => "timeout".to_string(),
+        ServerCommunicatorError::Terminate => "terminated".to_string(),
+        ServerCommunicatorError::Cancelled => "cancelled".to_string(),
+        ServerCommunicatorError::QueueFull => "queue full".to_string(),
+        ServerCommunicatorError::RedirectLoop(_) => "redirect loop".to_string(),
+        ServerCommunicatorError::TooManyRedirects(_) => "too many redirects".to_string(),
+    }
+}
+
+/// Collects the metrics from all workers
+#[derive(Default)]
+pub(crate) struct MetricsRecorder {
+    metrics: Mutex<Metrics>,
+}
+
+impl MetricsRecorder {
+    fn lock(&self) -> MutexGuard<'_, Metrics> {
+        self.metrics.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub fn connect(&self, time: Duration) {
+        self.lock().connect.record(time);
+    }
+
+    pub fn first_byte(&self, time: Duration) {
+        self.lock().first_byte.record(time);
+    }
+
+    pub fn total(&self, time: Duration) {
+        self.lock().total.record(time);
+    }
+
+    pub fn response(&self, response: &HttpResponse, body_len: usize) {
+        let mut metrics = self.lock();
+        metrics.bytes_received += body_len as u64;
+        if response.truncated {
+            metrics.truncated += 1;
+        }
+    }
+
+    pub fn error(&self, err: &ServerCommunicatorError) {
+        *self.lock().errors.entry(error_kind(err)).or_default() += 1;
+    }
+
+    pub fn snapshot(&self, retries: usize) -> Metrics {
+        Metrics {
+            retries,
+            ..self.lock().clone()
+        }
+    }
+}
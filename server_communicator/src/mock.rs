@@ -0,0 +1,260 @@
+use std::sync::{Arc, Mutex, MutexGuard, mpsc::channel};
+
+use http_message::http_messages::{request::HttpRequestMethod, response::ResponseObserver};
+
+use crate::{
+    CommunicatorChannels, HttpRequest, HttpResponse, ServerCommunicatorError, request_channel,
+    stream::EventObserver,
+};
+
+/// The answer of the mock to an expected request
+#[derive(Debug)]
+enum Reply {
+    Response(HttpResponse),
+    /// the head and the body are streamed, then the request fails
+    Error {
+        partial: Option<HttpResponse>,
+        error: ServerCommunicatorError,
+    },
+}
+
+fn response(status: u16, headers: &[(&str, &str)], body: &[u8]) -> HttpResponse {
+    let reason = match status {
+        200 => "OK",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        404 => "Not Found",
+        416 => "Range Not Satisfiable",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    };
+
+    let mut response = HttpResponse::new(status, reason, "HTTP/1.1");
+    headers
+        .iter()
+        .for_each(|(name, value)| response.add_header(name, value));
+    response.body = body.to_vec();
+    response
+}
+
+/// Request which the mock expects and its answer. By default any request is expected and answered with an empty 200
+#[derive(Debug)]
+pub struct Expectation {
+    method: Option<HttpRequestMethod>,
+    /// values are compared exactly
+    headers: Vec<(String, String)>,
+    reply: Reply,
+}
+
+impl Default for Expectation {
+    fn default() -> Self {
+        Self {
+            method: None,
+            headers: vec![],
+            reply: Reply::Response(response(200, &[], b"")),
+        }
+    }
+}
+
+impl Expectation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_method(mut self, method: HttpRequestMethod) -> Self {
+        self.method = Some(method);
+        self
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// the request has the header `Range: bytes=start-end`
+    pub fn with_range(self, start: usize, end: usize) -> Self {
+        self.with_header("Range", &format!("bytes={}-{}", start, end))
+    }
+
+    pub fn with_response(mut self, status: u16, headers: &[(&str, &str)], body: &[u8]) -> Self {
+        self.reply = Reply::Response(response(status, headers, body));
+        self
+    }
+
+    pub fn with_error(mut self, error: ServerCommunicatorError) -> Self {
+        self.reply = Reply::Error {
+            partial: None,
+            error,
+        };
+        self
+    }
+
+    /// the response is streamed up to `body`, then the request fails with `error`
+    pub fn with_partial_response(
+        mut self,
+        status: u16,
+        headers: &[(&str, &str)],
+        body: &[u8],
+        error: ServerCommunicatorError,
+    ) -> Self {
+        self.reply = Reply::Error {
+            partial: Some(response(status, headers, body)),
+            error,
+        };
+        self
+    }
+
+    fn matches(&self, request: &HttpRequest) -> bool {
+        self.method
+            .as_ref()
+            .is_none_or(|method| *method == request.method)
+            && self.headers.iter().all(|(name, value)| {
+                request
+                    .headers
+                    .get(&name.as_str().into())
+                    .is_some_and(|actual| actual.value == *value)
+            })
+    }
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    /// the expectations which were not used yet, in the scripted order
+    expectations: Vec<Expectation>,
+    /// requests which did not match any expectation
+    unexpected: Vec<String>,
+}
+
+fn describe(request: &HttpRequest) -> String {
+    let mut headers = request
+        .headers
+        .iter()
+        .map(|(name, value)| format!("{}: {}", name.name, value.value))
+        .collect::<Vec<_>>();
+    headers.sort_unstable();
+
+    format!(
+        "{:?} {} [{}]",
+        request.method,
+        request.request_target,
+        headers.join(", ")
+    )
+}
+
+/// Scripted replacement of [`crate::ServerCommunicator`] for the tests of its users.
+///
+/// Each request is answered by the first unused expectation, which matches it. Requests without an expectation fail with `InvalidResponse`
+#[derive(Debug, Default)]
+pub struct MockCommunicator {
+    expectations: Vec<Expectation>,
+}
+
+impl MockCommunicator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn expect(mut self, expectation: Expectation) -> Self {
+        self.expectations.push(expectation);
+        self
+    }
+
+    /// Starts answering the requests in a thread, the channels have the same shape as the channels of the communicator
+    pub fn start(self) -> (MockHandle, CommunicatorChannels) {
+        let (sender, requests) = request_channel();
+        let (responses, receiver) = channel();
+        let state = Arc::new(Mutex::new(MockState {
+            expectations: self.expectations,
+            unexpected: vec![],
+        }));
+
+        let handle = MockHandle(state.clone());
+        let streams = sender.streams();
+        let cancellation = sender.cancellation();
+        let priorities = sender.priorities();
+        std::thread::spawn(move || {
+            for (id, request) in requests {
+                // like a worker, the mock takes the request out of the queue
+                priorities.remove(id);
+                let reply = {
+                    let mut state = lock(&state);
+                    match state
+                        .expectations
+                        .iter()
+                        .position(|expected| expected.matches(&request))
+                    {
+                        Some(position) => Ok(state.expectations.remove(position).reply),
+                        None => {
+                            let request = describe(&request);
+                            state.unexpected.push(request.clone());
+                            Err(ServerCommunicatorError::InvalidResponse(format!(
+                                "unexpected request {}",
+                                request
+                            )))
+                        }
+                    }
+                };
+
+                // like the communicator, the body of a streamed response is only in the events
+                let events = streams.get(id);
+                let stream = |response: &mut HttpResponse| {
+                    if let Some(events) = &events {
+                        let mut observer = EventObserver::new(events);
+                        observer.head(response);
+                        observer.body(0, &std::mem::take(&mut response.body));
+                    }
+                };
+                let response = match reply {
+                    Ok(Reply::Response(mut response)) => {
+                        stream(&mut response);
+                        Ok(response)
+                    }
+                    Ok(Reply::Error { partial, error }) => {
+                        partial
+                            .into_iter()
+                            .for_each(|mut partial| stream(&mut partial));
+                        Err(error)
+                    }
+                    Err(error) => Err(error),
+                };
+
+                streams.finish(id, &response);
+                cancellation.finish(id);
+                if responses.send((id, response)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        (handle, (receiver, sender))
+    }
+}
+
+fn lock(state: &Mutex<MockState>) -> MutexGuard<'_, MockState> {
+    state.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Checks of the started mock
+#[derive(Debug, Clone)]
+pub struct MockHandle(Arc<Mutex<MockState>>);
+
+impl MockHandle {
+    /// Panics if some expectations were not used or some requests were not expected
+    pub fn assert_done(&self) {
+        let state = lock(&self.0);
+        let unused = state
+            .expectations
+            .iter()
+            .map(|expectation| format!("{:?} {:?}", expectation.method, expectation.headers))
+            .collect::<Vec<_>>();
+
+        assert!(
+            unused.is_empty() && state.unexpected.is_empty(),
+            "unused expectations: {:?}, unexpected requests: {:?}",
+            unused,
+            state.unexpected
+        );
+    }
+}
@@ -0,0 +1,192 @@
+use std::{
+    collections::VecDeque,
+    io::{BufWriter, Write},
+    time::Instant,
+};
+
+use http_message::http_messages::request::HttpRequestMethod;
+
+use crate::{
+    HttpRequest, HttpResponse, IdentifiedRequest, RequestId, Serialize, ServerCommunicatorError,
+    proxy,
+    worker::{Worker, has_connection_close, host_of, is_reusable, map_timeout},
+};
+
+/// Only requests without side effects can be sent again, when the pipeline is interrupted
+fn is_pipelinable(request: &HttpRequest) -> bool {
+    matches!(request.method, HttpRequestMethod::GET) && !has_connection_close(&request.headers)
+}
+
+/// A request with the retryable response to its pipelined attempt
+type Reissued = (
+    RequestId,
+    HttpRequest,
+    Result<HttpResponse, ServerCommunicatorError>,
+);
+
+fn same_host(first: &HttpRequest, second: &HttpRequest) -> bool {
+    matches!((host_of(first), host_of(second)), (Ok(first), Ok(second)) if first == second)
+}
+
+impl Worker {
+    /// Next request together with the requests to the same host, which are already queued, up to the pipeline depth
+    pub(crate) fn next_batch(&mut self) -> Option<Vec<IdentifiedRequest>> {
+        let first = self.next_request()?;
+        let depth = match self.shared.limiter.max_in_flight() {
+            Some(max_in_flight) => self.shared.config.pipeline_depth.min(max_in_flight),
+            None => self.shared.config.pipeline_depth,
+        };
+        // the recording keeps one exchange per request, so nothing is pipelined while recording
+        if depth <= 1
+            || self.shared.config.record.is_some()
+            || !is_pipelinable(&first.1)
+            || self.shared.is_shutdown()
+        {
+            return Some(vec![first]);
+        }
+
+        let mut batch = vec![first];
+        let mut requests = self
+            .shared
+            .requests
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+
+        // the worker does not wait for more requests, the pipeline is filled only with queued ones in the order of their priorities
+        while batch.len() < depth {
+            match requests
+                .pop_if(|request| is_pipelinable(request) && same_host(&batch[0].1, request))
+            {
+                Some(request) => batch.push(request),
+                None => break,
+            }
+        }
+
+        Some(batch)
+    }
+
+    /// Sends all requests of the batch on one connection and matches the responses in the order of the requests.
+    ///
+    /// If the server closes the connection in the middle, the unanswered requests are sent again one by one.
+    /// Returns false if nobody waits for responses anymore
+    pub(crate) fn pipeline(&self, batch: Vec<IdentifiedRequest>, started: Instant) -> bool {
+        let mut pending = VecDeque::from(batch);
+        let mut reissue = vec![];
+
+        match self.exchange_pipelined(&mut pending, &mut reissue, started) {
+            Ok(false) => return false,
+            Ok(true) => {}
+            Err(err) => {
+                self.shared.metrics.error(&err);
+                #[cfg(debug_assertions)]
+                println!(
+                    "The pipeline was interrupted: {}, {} requests are sent again",
+                    err,
+                    pending.len() + reissue.len()
+                );
+            }
+        }
+
+        // the pipelined attempt of the reissued requests counts against their retries
+        let reissued = reissue.into_iter().all(|(id, request, response)| {
+            let context = self.context(id);
+            let response = self.handle_retryable(&request, response, &context);
+            self.respond(id, started, response)
+        });
+        reissued
+            && pending.into_iter().all(|(id, request)| {
+                self.respond(id, started, self.handle(&request, &self.context(id)))
+            })
+    }
+
+    /// The answered requests are removed from `pending`, requests with retryable responses are moved to `reissue` together with them
+    fn exchange_pipelined(
+        &self,
+        pending: &mut VecDeque<IdentifiedRequest>,
+        reissue: &mut Vec<Reissued>,
+        started: Instant,
+    ) -> Result<bool, ServerCommunicatorError> {
+        let Some((_, first)) = pending.front() else {
+            return Ok(true);
+        };
+        let mut connection = self.shared.pool.get(host_of(first)?)?;
+        self.connected(&mut connection);
+        let deadline = self.deadline();
+        self.prepare(&mut connection, deadline)?;
+
+        // a cancelled request interrupts the pipeline, the rest is sent again one by one
+        let in_progress = pending
+            .iter()
+            .map(|(id, _)| {
+                self.shared
+                    .cancellation
+                    .start(*id, connection.stream().aborter())
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or(ServerCommunicatorError::Cancelled)?;
+
+        pending
+            .iter()
+            .for_each(|_| self.shared.limiter.before_request());
+
+        // all requests of the batch go to the same host, so either all or none are forwarded by the proxy
+        let proxy = self.shared.config.proxy.as_ref();
+        let forwarded = proxy.is_some_and(|proxy| proxy.forwards(first));
+        let outgoing = pending
+            .iter()
+            .map(|(_, request)| proxy::outgoing(proxy, request))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let sent = Instant::now();
+        let mut writer = BufWriter::new(connection.stream_mut());
+        outgoing
+            .iter()
+            .try_for_each(|request| request.serialize_into(&mut writer))
+            .and_then(|_| writer.flush())
+            .map_err(|err| map_timeout(err.into()))?;
+        drop(writer);
+        drop(outgoing);
+
+        // the requests are sent at once, so only the response to the first one shows the time to the first byte
+        let mut sent = Some(sent);
+        let policy = &self.shared.config.retry;
+        while let Some((id, request)) = pending.pop_front() {
+            let context = self.context(id);
+            let response = match self.read_response(
+                &mut connection,
+                sent.take(),
+                deadline,
+                context.events.as_ref(),
+            ) {
+                Ok(response) => response,
+                Err(err) => {
+                    pending.push_front((id, request));
+                    return Err(map_timeout(err));
+                }
+            };
+
+            // the rest of the requests can not be answered on this connection
+            let reusable = is_reusable(&request, &response);
+
+            let response = proxy::check_response(forwarded, response);
+            if policy.should_retry(&request, &response) {
+                reissue.push((id, request, response));
+            } else if !self.respond(
+                id,
+                started,
+                self.follow_redirects(&request, response, &context),
+            ) {
+                return Ok(false);
+            }
+
+            if !reusable {
+                return Ok(true);
+            }
+        }
+
+        // the connection must not be aborted after it is returned to the pool
+        drop(in_progress);
+        connection.keep_alive();
+        Ok(true)
+    }
+}
@@ -0,0 +1,251 @@
+use std::{
+    collections::HashMap,
+    io::BufReader,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::transport::{Stream, TcpTransport, Transport};
+
+/// Connection to a host, the reader keeps the data which was already read from the stream
+pub struct Connection {
+    pub reader: BufReader<Box<dyn Stream>>,
+    /// the connection was already used for another request
+    pub reused: bool,
+    /// time it took to establish the connection, taken by the first user
+    pub connect_time: Option<Duration>,
+}
+
+impl Connection {
+    pub fn new(stream: Box<dyn Stream>) -> Self {
+        Self {
+            reader: BufReader::new(stream),
+            reused: false,
+            connect_time: None,
+        }
+    }
+
+    pub fn stream(&self) -> &dyn Stream {
+        self.reader.get_ref().as_ref()
+    }
+
+    /// writing directly into the stream, the data buffered by the reader stays in place
+    pub fn stream_mut(&mut self) -> &mut dyn Stream {
+        self.reader.get_mut().as_mut()
+    }
+
+    /// checks whether the server closed the connection while it was idle
+    fn is_closed(&self) -> bool {
+        // the server must not send anything without a request
+        !self.reader.buffer().is_empty() || self.stream().is_closed()
+    }
+}
+
+struct IdleConnection {
+    connection: Connection,
+    since: Instant,
+}
+
+#[derive(Default)]
+struct PoolState {
+    idle: HashMap<String, Vec<IdleConnection>>,
+    /// amount of open connections for each host, both idle and in use
+    open: HashMap<String, usize>,
+}
+
+impl PoolState {
+    fn close(&mut self, host: &str) {
+        if let Some(open) = self.open.get_mut(host) {
+            *open -= 1;
+            if *open == 0 {
+                self.open.remove(host);
+            }
+        }
+    }
+
+    fn evict_expired(&mut self, idle_timeout: Duration) {
+        let mut closed = vec![];
+        self.idle.retain(|host, connections| {
+            connections.retain(|idle| {
+                let expired = idle.since.elapsed() >= idle_timeout;
+                if expired {
+                    closed.push(host.clone());
+                }
+                !expired
+            });
+            !connections.is_empty()
+        });
+
+        closed.iter().for_each(|host| self.close(host));
+    }
+}
+
+/// Keeps idle keep-alive connections for each host and limits the amount of open connections to a host.
+///
+/// Can be shared between threads.
+pub struct ConnectionPool {
+    state: Mutex<PoolState>,
+    available: Condvar,
+    idle_timeout: Duration,
+    max_per_host: usize,
+    connect_timeout: Option<Duration>,
+    transport: Arc<dyn Transport>,
+}
+
+impl ConnectionPool {
+    pub fn new(idle_timeout: Duration, max_per_host: usize) -> Self {
+        Self {
+            state: Mutex::new(PoolState::default()),
+            available: Condvar::new(),
+            idle_timeout,
+            max_per_host: max_per_host.max(1),
+            connect_timeout: None,
+            transport: Arc::new(TcpTransport::default()),
+        }
+    }
+
+    /// transport for new connections, TCP by default
+    pub fn with_transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    fn connect(&self, host: &str) -> std::io::Result<Connection> {
+        let start = Instant::now();
+        let mut connection = Connection::new(self.transport.connect(host, self.connect_timeout)?);
+        connection.connect_time = Some(start.elapsed());
+        Ok(connection)
+    }
+
+    /// timeout for establishing new connections
+    pub fn with_connect_timeout(mut self, connect_timeout: Option<Duration>) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PoolState> {
+        // the state stays consistent even if another thread panicked
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Idle connection to the host if there is a usable one, otherwise a new connection.
+    ///
+    /// Blocks while the host already has the maximum amount of connections in use.
+    pub fn get(&self, host: &str) -> std::io::Result<PooledConnection<'_>> {
+        let mut state = self.lock();
+
+        loop {
+            state.evict_expired(self.idle_timeout);
+
+            // the most recently used connection is the most likely to be alive
+            while let Some(idle) = state.idle.get_mut(host).and_then(Vec::pop) {
+                if idle.connection.is_closed() {
+                    state.close(host);
+                    continue;
+                }
+
+                let mut connection = idle.connection;
+                connection.reused = true;
+                return Ok(PooledConnection::new(self, host, connection));
+            }
+
+            let open = state.open.entry(host.to_string()).or_default();
+            if *open < self.max_per_host {
+                *open += 1;
+                drop(state);
+
+                return match self.connect(host) {
+                    Ok(connection) => Ok(PooledConnection::new(self, host, connection)),
+                    Err(err) => {
+                        self.release(host);
+                        Err(err)
+                    }
+                };
+            }
+
+            state = self
+                .available
+                .wait(state)
+                .unwrap_or_else(|err| err.into_inner());
+        }
+    }
+
+    fn put(&self, host: &str, connection: Connection) {
+        self.lock()
+            .idle
+            .entry(host.to_string())
+            .or_default()
+            .push(IdleConnection {
+                connection,
+                since: Instant::now(),
+            });
+        self.available.notify_all();
+    }
+
+    fn release(&self, host: &str) {
+        self.lock().close(host);
+        self.available.notify_all();
+    }
+
+    pub fn idle_count(&self, host: &str) -> usize {
+        self.lock().idle.get(host).map_or(0, Vec::len)
+    }
+
+    pub fn open_count(&self, host: &str) -> usize {
+        self.lock().open.get(host).copied().unwrap_or(0)
+    }
+}
+
+/// Connection taken from the pool. It is closed on drop, unless it was returned to the pool with [`PooledConnection::keep_alive`]
+pub struct PooledConnection<'a> {
+    pool: &'a ConnectionPool,
+    host: String,
+    connection: Option<Connection>,
+}
+
+impl<'a> PooledConnection<'a> {
+    fn new(pool: &'a ConnectionPool, host: &str, connection: Connection) -> Self {
+        Self {
+            pool,
+            host: host.to_string(),
+            connection: Some(connection),
+        }
+    }
+
+    /// return the connection to the pool, so the next request to the host can use it
+    pub fn keep_alive(mut self) {
+        if let Some(connection) = self.connection.take() {
+            self.pool.put(&self.host, connection);
+        }
+    }
+
+    /// replace the connection with a new one to the same host, the amount of open connections stays the same
+    pub fn reconnect(&mut self) -> std::io::Result<()> {
+        self.connection = Some(self.pool.connect(&self.host)?);
+        Ok(())
+    }
+}
+
+impl Deref for PooledConnection<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Self::Target {
+        // the connection is taken only in keep_alive, which consumes self
+        self.connection.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledConnection<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.connection.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledConnection<'_> {
+    fn drop(&mut self) {
+        if self.connection.is_some() {
+            self.pool.release(&self.host);
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Condvar, Mutex, MutexGuard,
+        mpsc::{Receiver, TryRecvError},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{HttpRequest, IdentifiedRequest, RequestId};
+
+/// Priority of a request, queued requests with a higher priority are handled first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Priority {
+    /// speculative requests, which can wait
+    Low,
+    #[default]
+    Normal,
+    High,
+    /// requests everything else depends on
+    Critical,
+}
+
+impl std::str::FromStr for Priority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(Self::Low),
+            "normal" => Ok(Self::Normal),
+            "high" => Ok(Self::High),
+            "critical" => Ok(Self::Critical),
+            _ => Err(format!(
+                "no such priority {}, use low, normal, high or critical",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    /// priorities of the queued requests with the moments they were sent
+    requests: HashMap<RequestId, (Priority, Instant)>,
+    /// counts the requests put into the channel, so the workers notice new ones without holding the scheduler
+    arrived: u64,
+    /// no more requests are queued, waiting senders give up
+    closed: bool,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    queue: Mutex<Queue>,
+    changed: Condvar,
+    /// requests in the channel and in the scheduler together
+    capacity: Option<usize>,
+}
+
+/// Priorities of the queued requests, shared by the request senders and the scheduler.
+///
+/// A request counts as queued from its sending until a worker takes it, so the channel and the scheduler share one capacity
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Priorities(Arc<QueueState>);
+
+impl Priorities {
+    pub fn bounded(capacity: usize) -> Self {
+        Self(Arc::new(QueueState {
+            capacity: Some(capacity),
+            ..Default::default()
+        }))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        self.0.queue.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn is_full(&self, queue: &Queue) -> bool {
+        self.0
+            .capacity
+            .is_some_and(|capacity| queue.requests.len() >= capacity)
+    }
+
+    /// Waits while the queue is full. False if the queue was closed
+    pub fn queued(&self, id: RequestId, priority: Priority) -> bool {
+        self.queued_at(id, priority, Instant::now())
+    }
+
+    pub fn queued_at(&self, id: RequestId, priority: Priority, sent: Instant) -> bool {
+        let mut queue = self.lock();
+        while !queue.closed && self.is_full(&queue) {
+            queue = self
+                .0
+                .changed
+                .wait(queue)
+                .unwrap_or_else(|err| err.into_inner());
+        }
+        if queue.closed {
+            return false;
+        }
+
+        queue.requests.insert(id, (priority, sent));
+        true
+    }
+
+    /// false if the queue is full
+    pub fn try_queued(&self, id: RequestId, priority: Priority) -> bool {
+        let mut queue = self.lock();
+        if self.is_full(&queue) {
+            return false;
+        }
+
+        queue.requests.insert(id, (priority, Instant::now()));
+        true
+    }
+
+    /// the request left the queue
+    pub fn remove(&self, id: RequestId) {
+        self.lock().requests.remove(&id);
+        self.0.changed.notify_all();
+    }
+
+    /// false if the request is not queued anymore
+    pub fn change(&self, id: RequestId, priority: Priority) -> bool {
+        match self.lock().requests.get_mut(&id) {
+            Some((queued, _)) => {
+                *queued = priority;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// the queued request was put into the channel
+    pub fn arrived(&self) {
+        self.lock().arrived += 1;
+        self.0.changed.notify_all();
+    }
+
+    /// amount of the requests, which arrived so far
+    pub fn arrivals(&self) -> u64 {
+        self.lock().arrived
+    }
+
+    /// Waits at most `timeout` for a request, which arrives after the first `arrivals` ones
+    pub fn wait_arrival(&self, arrivals: u64, timeout: Duration) {
+        let queue = self.lock();
+        let _ = self
+            .0
+            .changed
+            .wait_timeout_while(queue, timeout, |queue| {
+                !queue.closed && queue.arrived == arrivals
+            })
+            .unwrap_or_else(|err| err.into_inner());
+    }
+
+    /// the senders waiting for free space give up, later requests are not queued
+    pub fn close(&self) {
+        self.lock().closed = true;
+        self.0.changed.notify_all();
+    }
+}
+
+/// Takes the requests out of the channel and gives them to the workers by priority.
+///
+/// A waiting request gains one priority level for each `aging` interval, so low priority requests are not starved
+pub(crate) struct Scheduler {
+    requests: Receiver<IdentifiedRequest>,
+    priorities: Priorities,
+    /// requests taken out of the channel in the order they came
+    queued: Vec<IdentifiedRequest>,
+    aging: Option<Duration>,
+    disconnected: bool,
+}
+
+impl Scheduler {
+    pub fn new(
+        requests: Receiver<IdentifiedRequest>,
+        priorities: Priorities,
+        aging: Option<Duration>,
+    ) -> Self {
+        Self {
+            requests,
+            priorities,
+            queued: vec![],
+            aging,
+            disconnected: false,
+        }
+    }
+
+    /// takes all requests, which are already in the channel
+    fn fill(&mut self) {
+        loop {
+            match self.requests.try_recv() {
+                Ok(request) => self.queued.push(request),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.disconnected = true;
+                    break;
+                }
+            }
+        }
+    }
+
+    fn score(
+        &self,
+        priorities: &HashMap<RequestId, (Priority, Instant)>,
+        id: RequestId,
+        now: Instant,
+    ) -> f64 {
+        let (priority, sent) = priorities
+            .get(&id)
+            .copied()
+            .unwrap_or((Priority::default(), now));
+        let aged = match self.aging {
+            Some(aging) if !aging.is_zero() => {
+                now.duration_since(sent).as_secs_f64() / aging.as_secs_f64()
+            }
+            _ => 0.0,
+        };
+
+        priority as u8 as f64 + aged
+    }
+
+    /// The request with the highest priority, if it satisfies the condition. Earlier requests win with the same priority
+    pub fn pop_if(
+        &mut self,
+        condition: impl FnOnce(&HttpRequest) -> bool,
+    ) -> Option<IdentifiedRequest> {
+        self.fill();
+
+        let now = Instant::now();
+        let queue = self.priorities.lock();
+        let mut best: Option<(usize, f64)> = None;
+        for (position, (id, _)) in self.queued.iter().enumerate() {
+            let score = self.score(&queue.requests, *id, now);
+            if best.is_none_or(|(_, best)| score > best) {
+                best = Some((position, score));
+            }
+        }
+        drop(queue);
+
+        let (position, _) = best?;
+        if !condition(&self.queued[position].1) {
+            return None;
+        }
+        let request = self.queued.remove(position);
+        self.priorities.remove(request.0);
+        Some(request)
+    }
+
+    pub fn pop(&mut self) -> Option<IdentifiedRequest> {
+        self.pop_if(|_| true)
+    }
+
+    /// all request senders were dropped and no request is left
+    pub fn is_finished(&self) -> bool {
+        self.disconnected && self.queued.is_empty()
+    }
+
+    /// all queued requests, they are not handled anymore
+    pub fn drain(&mut self) -> Vec<IdentifiedRequest> {
+        while let Ok(request) = self.requests.try_recv() {
+            self.queued.push(request);
+        }
+
+        let drained = std::mem::take(&mut self.queued);
+        drained
+            .iter()
+            .for_each(|(id, _)| self.priorities.remove(*id));
+        drained
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        self.priorities.close();
+    }
+}
@@ -0,0 +1,257 @@
+use std::{borrow::Cow, io::ErrorKind, sync::Arc, time::Duration};
+
+use http_message::uri::{authority::Authority, target::RequestTarget};
+
+use crate::{
+    HttpRequest, HttpResponse, ServerCommunicatorError,
+    transport::{Stream, Transport, with_default_port},
+};
+
+/// upper limit for the head of the CONNECT response
+const MAX_CONNECT_RESPONSE: usize = 8 * 1024;
+
+/// How the requests go through the proxy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProxyMode {
+    /// requests are sent to the proxy in absolute-form (`GET http://host/path HTTP/1.1`)
+    #[default]
+    Forward,
+    /// a tunnel to the origin server is opened with CONNECT, requests go through it unchanged
+    Tunnel,
+}
+
+impl std::str::FromStr for ProxyMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "forward" => Ok(Self::Forward),
+            "tunnel" => Ok(Self::Tunnel),
+            _ => Err(format!("no such proxy mode {}, use forward or tunnel", s)),
+        }
+    }
+}
+
+/// Forward proxy for plain HTTP
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyConfig {
+    /// `host:port` of the proxy
+    pub address: String,
+    pub mode: ProxyMode,
+    /// hosts which are connected directly: names, domain suffixes (`example.com` and `.example.com` match the subdomains too) or `*` for all
+    pub no_proxy: Vec<String>,
+}
+
+impl ProxyConfig {
+    /// `address` can be given as `http://host:port/`, like in HTTP_PROXY
+    pub fn new(address: &str) -> Self {
+        let address = address.trim();
+        let address = address.strip_prefix("http://").unwrap_or(address);
+
+        Self {
+            address: address.trim_end_matches('/').to_string(),
+            mode: ProxyMode::default(),
+            no_proxy: vec![],
+        }
+    }
+
+    pub fn with_mode(mut self, mode: ProxyMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// comma separated list of hosts, like in NO_PROXY
+    pub fn with_no_proxy(mut self, no_proxy: &str) -> Self {
+        self.no_proxy = no_proxy
+            .split(',')
+            .map(|host| host.trim().to_ascii_lowercase())
+            .filter(|host| !host.is_empty())
+            .collect();
+        self
+    }
+
+    /// Proxy from HTTP_PROXY (or http_proxy) with the exceptions from NO_PROXY (or no_proxy), None if no proxy is set
+    pub fn from_env() -> Option<Self> {
+        let var = |names: [&str; 2]| names.into_iter().find_map(|name| std::env::var(name).ok());
+
+        let address = var(["HTTP_PROXY", "http_proxy"]).filter(|address| !address.is_empty())?;
+        let no_proxy = var(["NO_PROXY", "no_proxy"]).unwrap_or_default();
+        Some(Self::new(&address).with_no_proxy(&no_proxy))
+    }
+
+    /// `host` is the value of the Host header
+    pub fn is_used_for(&self, host: &str) -> bool {
+        let name = Authority::parse(host)
+            .map(|authority| authority.host)
+            .unwrap_or_else(|_| host.to_ascii_lowercase());
+
+        !self.no_proxy.iter().any(|exception| {
+            let domain = exception.trim_start_matches('.');
+            exception == "*"
+                || name == domain
+                || name
+                    .strip_suffix(domain)
+                    .is_some_and(|subdomain| subdomain.ends_with('.'))
+        })
+    }
+
+    /// the request has to be sent in absolute-form
+    pub(crate) fn forwards(&self, request: &HttpRequest) -> bool {
+        self.mode == ProxyMode::Forward
+            && crate::worker::host_of(request).is_ok_and(|host| self.is_used_for(host))
+    }
+}
+
+/// failure of the proxy, which is carried through the io errors of the transport
+#[derive(Debug)]
+struct ProxyFailure(String);
+
+impl std::fmt::Display for ProxyFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ProxyFailure {}
+
+fn proxy_error(kind: ErrorKind, message: String) -> std::io::Error {
+    std::io::Error::new(kind, ProxyFailure(message))
+}
+
+/// the io error was caused by the proxy
+pub(crate) fn is_proxy_error(err: &std::io::Error) -> bool {
+    err.get_ref().is_some_and(|err| err.is::<ProxyFailure>())
+}
+
+/// Request in absolute-form for the proxy, if it is needed
+pub(crate) fn outgoing<'r>(
+    proxy: Option<&ProxyConfig>,
+    request: &'r HttpRequest,
+) -> Result<Cow<'r, HttpRequest>, ServerCommunicatorError> {
+    let Some(path) = request.request_target.path() else {
+        return Ok(Cow::Borrowed(request));
+    };
+    if !proxy.is_some_and(|proxy| proxy.forwards(request)) {
+        return Ok(Cow::Borrowed(request));
+    }
+
+    let host = crate::worker::host_of(request)?;
+    let authority = Authority::parse(host).map_err(|err| {
+        proxy_error(
+            ErrorKind::InvalidInput,
+            format!("the host {:?} can not be sent to the proxy: {}", host, err),
+        )
+    })?;
+
+    let mut absolute = request.clone();
+    absolute.request_target = RequestTarget::Absolute {
+        scheme: "http".to_string(),
+        authority,
+        path: path.clone(),
+    };
+    Ok(Cow::Owned(absolute))
+}
+
+/// 407 from the proxy is a failure of the proxy, not a response of the origin server
+pub(crate) fn check_response(
+    forwarded: bool,
+    response: HttpResponse,
+) -> Result<HttpResponse, ServerCommunicatorError> {
+    if forwarded && response.result == 407 {
+        return Err(proxy_error(
+            ErrorKind::PermissionDenied,
+            format!(
+                "the proxy requires authentication: {} {}",
+                response.result, response.result_string
+            ),
+        )
+        .into());
+    }
+
+    Ok(response)
+}
+
+/// Connects to the hosts through the proxy, the hosts from `no_proxy` are connected directly
+#[derive(Debug)]
+pub struct ProxyTransport {
+    inner: Arc<dyn Transport>,
+    proxy: ProxyConfig,
+}
+
+impl ProxyTransport {
+    /// `inner` is used for the connections to the proxy and to the hosts without the proxy
+    pub fn new(inner: Arc<dyn Transport>, proxy: ProxyConfig) -> Self {
+        Self { inner, proxy }
+    }
+
+    /// Opens the tunnel to the host, the stream is connected to the host afterwards
+    fn tunnel(
+        stream: &mut dyn Stream,
+        host: &str,
+        timeout: Option<Duration>,
+    ) -> std::io::Result<()> {
+        let target = with_default_port(host);
+        stream.set_read_timeout(timeout)?;
+        stream.set_write_timeout(timeout)?;
+        write!(stream, "CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\n", target)?;
+        stream.flush()?;
+
+        // byte by byte, nothing after the head may be taken from the tunnel
+        let mut head = vec![];
+        let mut byte = [0_u8];
+        while !head.ends_with(b"\r\n\r\n") {
+            if head.len() >= MAX_CONNECT_RESPONSE {
+                return Err(proxy_error(
+                    ErrorKind::InvalidData,
+                    "the CONNECT response is too long".to_string(),
+                ));
+            }
+            if stream.read(&mut byte)? == 0 {
+                return Err(proxy_error(
+                    ErrorKind::UnexpectedEof,
+                    format!("the proxy closed the connection on CONNECT {}", target),
+                ));
+            }
+            head.push(byte[0]);
+        }
+
+        let head = String::from_utf8_lossy(&head);
+        let status_line = head.lines().next().unwrap_or_default();
+        match status_line.split(' ').nth(1) {
+            Some(status) if status.starts_with('2') && status.len() == 3 => Ok(()),
+            _ => Err(proxy_error(
+                ErrorKind::PermissionDenied,
+                format!("CONNECT {} was rejected: {}", target, status_line),
+            )),
+        }
+    }
+}
+
+impl Transport for ProxyTransport {
+    fn connect(&self, host: &str, timeout: Option<Duration>) -> std::io::Result<Box<dyn Stream>> {
+        if !self.proxy.is_used_for(host) {
+            return self.inner.connect(host, timeout);
+        }
+
+        let mut stream = self
+            .inner
+            .connect(&self.proxy.address, timeout)
+            .map_err(|err| {
+                proxy_error(
+                    err.kind(),
+                    format!("connecting to the proxy {}: {}", self.proxy.address, err),
+                )
+            })?;
+
+        if self.proxy.mode == ProxyMode::Tunnel {
+            Self::tunnel(stream.as_mut(), host, timeout).map_err(|err| {
+                match is_proxy_error(&err) {
+                    true => err,
+                    false => proxy_error(err.kind(), format!("CONNECT {}: {}", host, err)),
+                }
+            })?;
+        }
+
+        Ok(stream)
+    }
+}
@@ -0,0 +1,482 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    BodyFraming,
+    transport::{Aborter, LoopbackStream, Stream, Transport},
+};
+
+/// One request and the response to it, as they were sent over the connection
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Exchange {
+    /// number of the connection in the recording
+    pub connection: u64,
+    /// the moment the request was sent, since the start of the recording
+    pub sent_at: Duration,
+    /// time from the request to the first byte of the response
+    pub first_byte: Duration,
+    /// time from the request to the last byte of the response
+    pub duration: Duration,
+    /// the connection was closed (or failed) after the response
+    pub closed: bool,
+    pub request: Vec<u8>,
+    /// raw bytes of the response, truncated responses are kept as they are
+    pub response: Vec<u8>,
+}
+
+impl Exchange {
+    fn write_into(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        writeln!(
+            writer,
+            "exchange {} {} {} {} {} {} {}",
+            self.connection,
+            self.sent_at.as_micros(),
+            self.first_byte.as_micros(),
+            self.duration.as_micros(),
+            self.closed,
+            self.request.len(),
+            self.response.len()
+        )?;
+        writer.write_all(&self.request)?;
+        writer.write_all(&self.response)?;
+        writeln!(writer)
+    }
+
+    /// None at the end of the recording
+    fn read_from(reader: &mut impl BufRead) -> std::io::Result<Option<Self>> {
+        let invalid = |msg: &str| std::io::Error::new(ErrorKind::InvalidData, msg.to_string());
+
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let fields = line
+            .trim_end()
+            .strip_prefix("exchange ")
+            .ok_or_else(|| invalid("the recording entry must start with 'exchange'"))?
+            .split(' ')
+            .collect::<Vec<_>>();
+        let [
+            connection,
+            sent_at,
+            first_byte,
+            duration,
+            closed,
+            request_len,
+            response_len,
+        ] = fields[..]
+        else {
+            return Err(invalid("invalid amount of fields in the recording entry"));
+        };
+
+        let number = |value: &str| {
+            value
+                .parse::<u64>()
+                .map_err(|_| invalid(&format!("invalid number in the recording: {}", value)))
+        };
+        let micros = |value: &str| number(value).map(Duration::from_micros);
+        let bytes = |reader: &mut dyn Read, len: &str| -> std::io::Result<Vec<u8>> {
+            let mut bytes = vec![0; number(len)? as usize];
+            reader.read_exact(&mut bytes)?;
+            Ok(bytes)
+        };
+
+        let exchange = Self {
+            connection: number(connection)?,
+            sent_at: micros(sent_at)?,
+            first_byte: micros(first_byte)?,
+            duration: micros(duration)?,
+            closed: closed == "true",
+            request: bytes(reader, request_len)?,
+            response: bytes(reader, response_len)?,
+        };
+
+        let mut separator = [0];
+        reader.read_exact(&mut separator)?;
+        Ok(Some(exchange))
+    }
+}
+
+/// Reads all exchanges of the recording
+pub fn read_recording(path: impl AsRef<Path>) -> std::io::Result<Vec<Exchange>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut exchanges = vec![];
+
+    while let Some(exchange) = Exchange::read_from(&mut reader)? {
+        exchanges.push(exchange);
+    }
+
+    Ok(exchanges)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+struct Recorder {
+    file: Mutex<BufWriter<File>>,
+    started: Instant,
+    connections: Mutex<u64>,
+    /// the first error while writing the file, all later requests fail with it
+    failed: Mutex<Option<(ErrorKind, String)>>,
+}
+
+impl Recorder {
+    fn write(&self, exchange: &Exchange) {
+        let mut file = lock(&self.file);
+        if let Err(err) = exchange.write_into(&mut *file).and_then(|_| file.flush()) {
+            lock(&self.failed).get_or_insert((err.kind(), err.to_string()));
+        }
+    }
+
+    /// the recording would miss exchanges after an error, so no more traffic goes through it
+    fn check(&self) -> std::io::Result<()> {
+        match &*lock(&self.failed) {
+            Some((kind, msg)) => Err(std::io::Error::new(
+                *kind,
+                format!("the exchange could not be recorded: {}", msg),
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Wraps another transport and writes every request and response sent over it into a file.
+///
+/// When the file can not be written, the later requests fail with the error
+pub struct RecordingTransport {
+    inner: Arc<dyn Transport>,
+    recorder: Arc<Recorder>,
+}
+
+impl std::fmt::Debug for RecordingTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "RecordingTransport({:?})", self.inner)
+    }
+}
+
+impl RecordingTransport {
+    /// the file is truncated, if it exists
+    pub fn create(inner: Arc<dyn Transport>, path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self {
+            inner,
+            recorder: Arc::new(Recorder {
+                file: Mutex::new(BufWriter::new(File::create(path)?)),
+                started: Instant::now(),
+                connections: Mutex::new(0),
+                failed: Mutex::new(None),
+            }),
+        })
+    }
+}
+
+impl Transport for RecordingTransport {
+    fn connect(&self, host: &str, timeout: Option<Duration>) -> std::io::Result<Box<dyn Stream>> {
+        self.recorder.check()?;
+        let inner = self.inner.connect(host, timeout)?;
+
+        let connection = {
+            let mut connections = lock(&self.recorder.connections);
+            *connections += 1;
+            *connections - 1
+        };
+
+        Ok(Box::new(RecordingStream {
+            inner,
+            recorder: self.recorder.clone(),
+            connection,
+            current: None,
+        }))
+    }
+}
+
+struct RecordingStream {
+    inner: Box<dyn Stream>,
+    recorder: Arc<Recorder>,
+    connection: u64,
+    /// the exchange in progress and the moment its request was sent
+    current: Option<(Exchange, Instant)>,
+}
+
+impl RecordingStream {
+    fn finish(&mut self, closed: bool) {
+        if let Some((mut exchange, _)) = self.current.take() {
+            exchange.closed = closed;
+            self.recorder.write(&exchange);
+        }
+    }
+}
+
+impl Read for RecordingStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let result = self.inner.read(buf);
+
+        match &result {
+            Ok(0) => self.finish(true),
+            Ok(len) => {
+                if let Some((exchange, sent)) = &mut self.current {
+                    if exchange.response.is_empty() {
+                        exchange.first_byte = sent.elapsed();
+                    }
+                    exchange.duration = sent.elapsed();
+                    exchange.response.extend_from_slice(&buf[..*len]);
+                }
+            }
+            // the response can still come after a timeout
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(_) => self.finish(true),
+        }
+
+        result
+    }
+}
+
+impl Write for RecordingStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // the next request on a keep-alive connection starts a new exchange
+        if self
+            .current
+            .as_ref()
+            .is_some_and(|(exchange, _)| !exchange.response.is_empty())
+        {
+            self.finish(false);
+        }
+        self.recorder.check()?;
+        let len = self.inner.write(buf)?;
+
+        let (exchange, _) = self.current.get_or_insert_with(|| {
+            (
+                Exchange {
+                    connection: self.connection,
+                    sent_at: self.recorder.started.elapsed(),
+                    ..Default::default()
+                },
+                Instant::now(),
+            )
+        });
+        exchange.request.extend_from_slice(&buf[..len]);
+
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Stream for RecordingStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.inner.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.inner.set_write_timeout(timeout)
+    }
+
+    fn is_closed(&self) -> bool {
+        self.inner.is_closed()
+    }
+
+    fn aborter(&self) -> Option<Aborter> {
+        self.inner.aborter()
+    }
+}
+
+impl Drop for RecordingStream {
+    fn drop(&mut self) {
+        self.finish(false);
+    }
+}
+
+/// Requests are matched by the start line, headers and body, the order of headers does not matter
+fn request_key(request: &[u8]) -> String {
+    let request = String::from_utf8_lossy(request);
+    let (head, body) = request.split_once("\r\n\r\n").unwrap_or((&request, ""));
+    let mut lines = head.split("\r\n").filter(|line| !line.is_empty());
+
+    let start_line = lines.next().unwrap_or_default();
+    let mut headers = lines.collect::<Vec<_>>();
+    headers.sort_unstable();
+
+    format!("{}\n{}\n\n{}", start_line, headers.join("\n"), body)
+}
+
+/// How the body of the request is framed (RFC 9112 6.3), a request without Content-Length and Transfer-Encoding has no body.
+///
+/// None if the framing is invalid
+fn request_framing(head: &[u8]) -> Option<BodyFraming> {
+    let head = String::from_utf8_lossy(head);
+    let header = |name: &str| {
+        head.split("\r\n")
+            .skip(1)
+            .filter_map(|line| line.split_once(':'))
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim().to_string())
+    };
+
+    match (header("Transfer-Encoding"), header("Content-Length")) {
+        (Some(_), Some(_)) => None,
+        // the length of a request, which is not chunked at last, can not be determined
+        (Some(encoding), None) => encoding
+            .rsplit(',')
+            .next()
+            .is_some_and(|last| last.trim().eq_ignore_ascii_case("chunked"))
+            .then_some(BodyFraming::Chunked),
+        (None, Some(length)) => length.parse().ok().map(BodyFraming::Length),
+        (None, None) => Some(BodyFraming::Empty),
+    }
+}
+
+/// Appends `len` bytes to the body, the length comes from the request, so nothing is allocated in advance
+fn read_exactly(reader: &mut impl BufRead, len: usize, body: &mut Vec<u8>) -> std::io::Result<()> {
+    if reader.take(len as u64).read_to_end(body)? < len {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+/// Reads the chunked body as it is sent, with the sizes of the chunks and the trailers
+fn read_chunked(reader: &mut impl BufRead, body: &mut Vec<u8>) -> std::io::Result<()> {
+    loop {
+        let start = body.len();
+        if reader.read_until(b'\n', body)? == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+
+        // chunk extensions are ignored
+        let line = String::from_utf8_lossy(&body[start..]);
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16)
+            .map_err(|err| std::io::Error::new(ErrorKind::InvalidData, err))?;
+        if size == 0 {
+            break;
+        }
+
+        // the chunk is followed by CRLF
+        read_exactly(reader, size.saturating_add(2), body)?;
+    }
+
+    // trailer section ends with an empty line
+    loop {
+        let start = body.len();
+        if reader.read_until(b'\n', body)? == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        if body[start..] == *b"\r\n" {
+            return Ok(());
+        }
+    }
+}
+
+/// Answers requests with the responses from a recording. Each recorded exchange is used once, in the recorded order.
+///
+/// Requests which are not in the recording are answered by closing the connection
+#[derive(Debug, Clone)]
+pub struct ReplayTransport {
+    exchanges: Arc<Mutex<HashMap<String, VecDeque<Exchange>>>>,
+    recorded_speed: bool,
+}
+
+impl ReplayTransport {
+    pub fn new(exchanges: Vec<Exchange>) -> Self {
+        let mut by_request: HashMap<String, VecDeque<Exchange>> = HashMap::new();
+        for exchange in exchanges {
+            by_request
+                .entry(request_key(&exchange.request))
+                .or_default()
+                .push_back(exchange);
+        }
+
+        Self {
+            exchanges: Arc::new(Mutex::new(by_request)),
+            recorded_speed: false,
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self::new(read_recording(path)?))
+    }
+
+    /// responses are delayed like in the recording, otherwise they are sent immediately
+    pub fn with_recorded_speed(mut self, recorded_speed: bool) -> Self {
+        self.recorded_speed = recorded_speed;
+        self
+    }
+
+    /// amount of recorded exchanges, which were not replayed yet
+    pub fn remaining(&self) -> usize {
+        lock(&self.exchanges).values().map(VecDeque::len).sum()
+    }
+
+    fn serve(&self, stream: LoopbackStream) {
+        let mut reader = BufReader::new(stream);
+
+        loop {
+            let mut request = vec![];
+            loop {
+                let len = match reader.read_until(b'\n', &mut request) {
+                    Ok(len) => len,
+                    Err(_) => return,
+                };
+                if len == 0 {
+                    return;
+                }
+                if request.ends_with(b"\r\n\r\n") {
+                    break;
+                }
+            }
+
+            // the body is a part of the request, the next one starts after it
+            let read = match request_framing(&request) {
+                Some(BodyFraming::Length(length)) => {
+                    read_exactly(&mut reader, length, &mut request)
+                }
+                Some(BodyFraming::Chunked) => read_chunked(&mut reader, &mut request),
+                Some(_) => Ok(()),
+                None => return,
+            };
+            if read.is_err() {
+                return;
+            }
+
+            let exchange = lock(&self.exchanges)
+                .get_mut(&request_key(&request))
+                .and_then(VecDeque::pop_front);
+            let Some(exchange) = exchange else {
+                return;
+            };
+
+            if self.recorded_speed {
+                std::thread::sleep(exchange.first_byte);
+            }
+            if reader.get_mut().write_all(&exchange.response).is_err() {
+                return;
+            }
+            if self.recorded_speed {
+                std::thread::sleep(exchange.duration.saturating_sub(exchange.first_byte));
+            }
+
+            if exchange.closed {
+                return;
+            }
+        }
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn connect(&self, _host: &str, _timeout: Option<Duration>) -> std::io::Result<Box<dyn Stream>> {
+        let (client, server) = LoopbackStream::pair();
+
+        let transport = self.clone();
+        std::thread::spawn(move || transport.serve(server));
+
+        Ok(Box::new(client))
+    }
+}
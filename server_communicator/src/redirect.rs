@@ -0,0 +1,151 @@
+use http_message::{
+    http_messages::{path::Path, request::HttpRequestMethod},
+    uri::target::RequestTarget,
+};
+
+use crate::{HttpRequest, HttpResponse, ServerCommunicatorError, worker::host_of};
+
+/// How redirects (301, 302, 303, 307 and 308) are followed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RedirectPolicy {
+    /// maximum amount of redirects of one request, 0 returns the redirect responses to the caller
+    pub max_hops: usize,
+}
+
+impl RedirectPolicy {
+    pub fn none() -> Self {
+        Self { max_hops: 0 }
+    }
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> Self {
+        Self { max_hops: 5 }
+    }
+}
+
+pub(crate) fn is_redirect(response: &HttpResponse) -> bool {
+    matches!(response.result, 301 | 302 | 303 | 307 | 308)
+}
+
+fn location(response: &HttpResponse) -> Result<&str, ServerCommunicatorError> {
+    response
+        .headers
+        .get(&"Location".into())
+        .map(|value| value.value.trim())
+        .ok_or_else(|| {
+            ServerCommunicatorError::InvalidResponse(format!(
+                "redirect {} without Location",
+                response.result
+            ))
+        })
+}
+
+/// The location starts with `<scheme>://`, the scheme is ALPHA *( ALPHA / DIGIT / "+" / "-" / "." ) (RFC 3986 3.1)
+fn is_absolute(location: &str) -> bool {
+    location.split_once("://").is_some_and(|(scheme, _)| {
+        let mut chars = scheme.chars();
+        chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+            && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    })
+}
+
+/// Host and path of the location, relative locations are resolved against the request
+fn resolve(
+    request: &HttpRequest,
+    location: &str,
+) -> Result<(String, Path), ServerCommunicatorError> {
+    let invalid = |reason: String| {
+        ServerCommunicatorError::InvalidResponse(format!(
+            "invalid Location {:?}: {}",
+            location, reason
+        ))
+    };
+
+    // the fragment is never sent to the server
+    let location = location.split('#').next().unwrap_or_default();
+    let location = match location.strip_prefix("//") {
+        Some(rest) => format!("http://{}", rest),
+        None => location.to_string(),
+    };
+
+    let current = request.request_target.path();
+    let target = if location.starts_with('/') || is_absolute(&location) {
+        RequestTarget::parse(&location).map_err(|err| invalid(err.to_string()))?
+    } else if location.is_empty() {
+        // the same resource (RFC 3986 5.2.2)
+        request.request_target.clone()
+    } else if location.starts_with('?') {
+        // only the query of the current path is replaced
+        let current = current.map_or("/", |path| &path.path);
+        Path::new(format!("{}{}", current, location))
+            .map_err(|err| invalid(err.to_string()))?
+            .into()
+    } else {
+        // relative to the directory of the current path
+        let current = current.map_or("/", |path| &path.path);
+        let directory = &current[..current.rfind('/').map_or(0, |end| end + 1)];
+        Path::new(format!("{}{}", directory, location))
+            .map_err(|err| invalid(err.to_string()))?
+            .into()
+    };
+
+    match target {
+        RequestTarget::Origin(path) => Ok((host_of(request)?.to_string(), path)),
+        RequestTarget::Absolute {
+            scheme,
+            authority,
+            path,
+        } if scheme == "http" => Ok((authority.to_string(), path)),
+        RequestTarget::Absolute { scheme, .. } => {
+            Err(invalid(format!("the scheme {} is not supported", scheme)))
+        }
+        target => Err(invalid(format!("{} is not a location", target))),
+    }
+}
+
+/// Request to the location of the redirect response. All headers (Range too) are kept, except Host.
+/// Credentials are not sent to another host.
+///
+/// 303, and 301/302 after POST, change the method to GET without a body, like browsers do
+pub(crate) fn redirected(
+    request: &HttpRequest,
+    response: &HttpResponse,
+) -> Result<HttpRequest, ServerCommunicatorError> {
+    let (host, path) = resolve(request, location(response)?)?;
+
+    let mut redirected = request.clone();
+    redirected.request_target = path.into();
+    if !host_of(request)?.eq_ignore_ascii_case(&host) {
+        for header in ["Authorization", "Cookie", "Proxy-Authorization"] {
+            redirected.headers.remove(&header.into());
+        }
+    }
+    redirected.headers.remove(&"Host".into());
+    redirected.add_header("Host", &host);
+
+    let to_get = match response.result {
+        303 => true,
+        301 | 302 => matches!(redirected.method, HttpRequestMethod::POST),
+        _ => false,
+    };
+    if to_get {
+        redirected.method = HttpRequestMethod::GET;
+        redirected.body.clear();
+        for header in ["Content-Length", "Content-Type", "Transfer-Encoding"] {
+            redirected.headers.remove(&header.into());
+        }
+    }
+
+    Ok(redirected)
+}
+
+/// identifies the request for the loop detection
+pub(crate) fn visit_key(request: &HttpRequest) -> String {
+    format!(
+        "{:?} {}{}",
+        request.method,
+        host_of(request).unwrap_or_default(),
+        request.request_target
+    )
+}
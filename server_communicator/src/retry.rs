@@ -0,0 +1,146 @@
+use std::{
+    cell::Cell,
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io::ErrorKind,
+    time::Duration,
+};
+
+use http_message::http_messages::request::HttpRequestMethod;
+
+use crate::{HttpRequest, HttpResponse, ServerCommunicatorError};
+
+/// Timeouts of one request, `None` means no timeout
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeoutConfig {
+    /// establishing of a new connection
+    pub connect: Option<Duration>,
+    /// waiting for the data from the server
+    pub read: Option<Duration>,
+    /// sending the request
+    pub write: Option<Duration>,
+    /// the whole request including all retries and backoffs, counted from the moment a worker takes the request
+    pub overall: Option<Duration>,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self {
+            connect: Some(Duration::from_secs(5)),
+            read: Some(Duration::from_secs(10)),
+            write: Some(Duration::from_secs(10)),
+            overall: Some(Duration::from_secs(30)),
+        }
+    }
+}
+
+/// Decides whether a failed request is repeated and how long to wait before the next attempt
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// amount of attempts including the first one, 1 disables retries
+    pub max_attempts: usize,
+    /// delay after the first failed attempt
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// the delay is multiplied by it after each failed attempt
+    pub multiplier: f64,
+    /// part of the delay (from 0 to 1) which is random, so the retries of different requests do not happen at once
+    pub jitter: f64,
+    /// responses with these status codes are repeated
+    pub retryable_status: Vec<u16>,
+    /// errors for which the request is repeated
+    pub retryable_error: fn(&ServerCommunicatorError) -> bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            multiplier: 2.0,
+            jitter: 0.5,
+            retryable_status: vec![408, 429, 500, 502, 503, 504],
+            retryable_error: Self::is_transient,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// policy without retries
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Failures of the network and timeouts, which may not happen on the next attempt
+    pub fn is_transient(err: &ServerCommunicatorError) -> bool {
+        match err {
+            ServerCommunicatorError::TcpError(err) | ServerCommunicatorError::ProxyError(err) => {
+                matches!(
+                    err.kind(),
+                    ErrorKind::ConnectionRefused
+                        | ErrorKind::ConnectionReset
+                        | ErrorKind::ConnectionAborted
+                        | ErrorKind::BrokenPipe
+                        | ErrorKind::UnexpectedEof
+                        | ErrorKind::Interrupted
+                        | ErrorKind::TimedOut
+                )
+            }
+            ServerCommunicatorError::TimeOutError(_) => true,
+            _ => false,
+        }
+    }
+
+    /// POST requests are not repeated, as the server could have already handled them
+    pub fn should_retry(
+        &self,
+        request: &HttpRequest,
+        response: &Result<HttpResponse, ServerCommunicatorError>,
+    ) -> bool {
+        if matches!(request.method, HttpRequestMethod::POST) {
+            return false;
+        }
+
+        match response {
+            Ok(response) => self.retryable_status.contains(&response.result),
+            Err(err) => (self.retryable_error)(err),
+        }
+    }
+
+    /// Delay before the next attempt, `attempt` is the number of the failed attempt starting from 1
+    pub fn backoff(&self, attempt: usize) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as usize) as i32;
+        let delay = (self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent))
+            .min(self.max_backoff.as_secs_f64());
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let delay = delay * (1.0 - jitter * random_fraction());
+
+        // a negative or not finite multiplier must not make the conversion panic
+        Duration::try_from_secs_f64(delay.max(0.0))
+            .map_or(self.max_backoff, |delay| delay.min(self.max_backoff))
+    }
+}
+
+thread_local! {
+    /// state of the xorshift generator of the thread, seeded with the random keys of a RandomState, it is never 0
+    static RANDOM: Cell<u64> = Cell::new(RandomState::new().build_hasher().finish() | 1);
+}
+
+/// random number from 0 to 1 (excluded), the jitter only has to spread the retries, so xorshift64 is enough
+fn random_fraction() -> f64 {
+    RANDOM.with(|state| {
+        let mut x = state.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+
+        // the upper 53 bits fit into the mantissa
+        (x >> 11) as f64 / (1u64 << 53) as f64
+    })
+}
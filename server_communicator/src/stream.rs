@@ -0,0 +1,99 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        mpsc::{Receiver, Sender, channel},
+    },
+};
+
+use http_message::http_messages::response::ResponseObserver;
+
+use crate::{HttpResponse, RequestId, ServerCommunicatorError};
+
+/// Part of the response to a request sent with [`crate::RequestSender::send_streaming`], as it comes from the server.
+///
+/// A repeated (retried or redirected) attempt starts with a new `Head` and its data starts again from offset 0
+#[derive(Debug)]
+pub enum BodyEvent {
+    /// head of a response, its body is empty
+    Head(HttpResponse),
+    /// bytes of the body starting at `offset` of the body
+    Data { offset: usize, bytes: Vec<u8> },
+    /// the request is finished, `truncated` if the whole body of the last response was not received
+    End { truncated: bool },
+}
+
+/// Senders of the body events for the requests, which are streamed
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Streams(Arc<Mutex<HashMap<RequestId, Sender<BodyEvent>>>>);
+
+impl Streams {
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<RequestId, Sender<BodyEvent>>> {
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub fn subscribe(&self, id: RequestId) -> Receiver<BodyEvent> {
+        let (sender, receiver) = channel();
+        self.lock().insert(id, sender);
+        receiver
+    }
+
+    /// the request was not sent, so it is not streamed
+    pub fn unsubscribe(&self, id: RequestId) {
+        self.lock().remove(&id);
+    }
+
+    pub fn get(&self, id: RequestId) -> Option<Sender<BodyEvent>> {
+        self.lock().get(&id).cloned()
+    }
+
+    /// sends the end of the stream and forgets the request
+    pub fn finish(&self, id: RequestId, response: &Result<HttpResponse, ServerCommunicatorError>) {
+        if let Some(sender) = self.lock().remove(&id) {
+            let truncated = !matches!(response, Ok(response) if !response.truncated);
+            let _ = sender.send(BodyEvent::End { truncated });
+        }
+    }
+}
+
+/// Forwards the parts of the response as they are read, nobody may wait for them anymore.
+/// The body is taken by the events, so it is not kept in the response
+pub(crate) struct EventObserver<'a> {
+    events: &'a Sender<BodyEvent>,
+    /// bytes of the body, which were forwarded
+    pub received: usize,
+}
+
+impl<'a> EventObserver<'a> {
+    pub fn new(events: &'a Sender<BodyEvent>) -> Self {
+        Self {
+            events,
+            received: 0,
+        }
+    }
+}
+
+impl ResponseObserver for EventObserver<'_> {
+    fn head(&mut self, response: &HttpResponse) {
+        let _ = self.events.send(BodyEvent::Head(HttpResponse {
+            protocol: response.protocol.clone(),
+            result: response.result,
+            result_string: response.result_string.clone(),
+            headers: response.headers.clone(),
+            body: vec![],
+            truncated: false,
+        }));
+    }
+
+    fn body(&mut self, offset: usize, data: &[u8]) {
+        self.received += data.len();
+        let _ = self.events.send(BodyEvent::Data {
+            offset,
+            bytes: data.to_vec(),
+        });
+    }
+
+    fn takes_body(&self) -> bool {
+        true
+    }
+}
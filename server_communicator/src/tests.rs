@@ -0,0 +1,2211 @@
+use std::{
+    collections::HashSet,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use crate::*;
+use http_message::http_messages::request::HttpRequestMethod;
+
+/// reads the request head, false if the connection was closed
+fn read_request(reader: &mut impl BufRead) -> bool {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            return false;
+        }
+        if line == "\r\n" {
+            return true;
+        }
+    }
+}
+
+/// Answers `responses` requests on the stream with the body "data", then closes it
+fn answer_requests(stream: impl Read + Write, responses: usize) {
+    let mut reader = BufReader::new(stream);
+    for _ in 0..responses {
+        if !read_request(&mut reader) {
+            return;
+        }
+
+        let _ = reader.get_mut().write_all(DATA_RESPONSE.as_bytes());
+    }
+}
+
+const DATA_RESPONSE: &str = "HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\ndata";
+
+/// path of each received request with its Range header
+type ReceivedRequests = Arc<Mutex<Vec<(String, Option<String>)>>>;
+
+/// Request, which the stub server asks the reply for
+struct StubRequest {
+    path: String,
+    /// number of the requests received before on all connections
+    index: usize,
+    /// number of the requests received before on the same connection
+    on_connection: usize,
+}
+
+/// Reply of the stub server to a request
+enum Reply {
+    Send(String),
+    /// sends the response and closes the connection
+    Close(String),
+    /// keeps the connection open without an answer
+    Hold,
+}
+
+struct StubServer {
+    addr: String,
+    /// number of the accepted connections
+    accepted: Arc<AtomicUsize>,
+    requests: ReceivedRequests,
+}
+
+/// Server which replies to each request with the reply from `answer`
+fn stub_server(answer: impl Fn(&StubRequest) -> Reply + Send + Sync + 'static) -> StubServer {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let server = StubServer {
+        addr: listener.local_addr().unwrap().to_string(),
+        accepted: Arc::new(AtomicUsize::new(0)),
+        requests: Arc::new(Mutex::new(vec![])),
+    };
+
+    let (answer, accepted, received) = (
+        Arc::new(answer),
+        server.accepted.clone(),
+        server.requests.clone(),
+    );
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { break };
+            accepted.fetch_add(1, Ordering::SeqCst);
+            let (answer, received) = (answer.clone(), received.clone());
+
+            std::thread::spawn(move || {
+                let mut reader = BufReader::new(stream);
+                for on_connection in 0.. {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                        return;
+                    }
+                    let path = line.split(' ').nth(1).unwrap_or_default().to_string();
+
+                    let mut range = None;
+                    loop {
+                        line.clear();
+                        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                            return;
+                        }
+                        if line == "\r\n" {
+                            break;
+                        }
+                        if let Some(value) = line.strip_prefix("Range: ") {
+                            range = Some(value.trim().to_string());
+                        }
+                    }
+
+                    let index = {
+                        let mut received = received.lock().unwrap();
+                        received.push((path.clone(), range));
+                        received.len() - 1
+                    };
+                    let request = StubRequest {
+                        path,
+                        index,
+                        on_connection,
+                    };
+                    match answer(&request) {
+                        Reply::Send(response) => {
+                            let _ = reader.get_mut().write_all(response.as_bytes());
+                        }
+                        Reply::Close(response) => {
+                            let _ = reader.get_mut().write_all(response.as_bytes());
+                            return;
+                        }
+                        Reply::Hold => {}
+                    }
+                }
+            });
+        }
+    });
+
+    server
+}
+
+/// Server which answers `responses_per_connection` requests on each connection with the body "data", then closes it.
+/// Returns its address and the counter of accepted connections
+fn keep_alive_server(responses_per_connection: usize) -> (String, Arc<AtomicUsize>) {
+    let server = stub_server(move |request| {
+        if request.on_connection + 1 == responses_per_connection {
+            Reply::Close(DATA_RESPONSE.to_string())
+        } else {
+            Reply::Send(DATA_RESPONSE.to_string())
+        }
+    });
+    (server.addr, server.accepted)
+}
+
+/// Server which answers the requests with the given status codes one by one, on a separate connection each.
+/// Requests after the last status code are not answered. Returns its address and the received requests
+fn status_server(statuses: Vec<u16>) -> (String, ReceivedRequests) {
+    let server = stub_server(move |request| match statuses.get(request.index) {
+        Some(status) => Reply::Close(format!(
+            "HTTP/1.1 {} Status\r\nContent-Length: 4\r\nConnection: close\r\n\r\ndata",
+            status
+        )),
+        None => Reply::Hold,
+    });
+    (server.addr, server.requests)
+}
+
+/// Server which answers each request with the response for its path from `route`.
+/// Returns its address and the received requests
+fn route_server(
+    route: impl Fn(&str) -> String + Send + Sync + 'static,
+) -> (String, ReceivedRequests) {
+    let server = stub_server(move |request| Reply::Send(route(&request.path)));
+    (server.addr, server.requests)
+}
+
+/// Starts a communicator with the config, returns its handle and channels
+fn start_communicator(config: CommunicatorConfig) -> (CommunicatorHandle, CommunicatorChannels) {
+    let (communicator, channels) = ServerCommunicator::with_config(config).unwrap();
+    (communicator.start(), channels)
+}
+
+fn request(addr: &str) -> HttpRequest {
+    let mut request = HttpRequest::new(
+        HttpRequestMethod::GET,
+        http_message::http_messages::path::Path::default(),
+        "HTTP/1.1",
+    );
+    request.add_header("Host", addr);
+    request
+}
+
+#[test]
+fn test_keep_alive_reuse() {
+    let (addr, accepted) = keep_alive_server(usize::MAX);
+
+    let (communicator, (receiver, sender)) = ServerCommunicator::new().unwrap();
+    communicator.start();
+
+    for _ in 0..3 {
+        let id = sender.send(request(&addr)).unwrap();
+        let (response_id, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(response_id, id);
+        assert_eq!(response.unwrap().body, b"data");
+    }
+
+    assert_eq!(accepted.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_reconnect_after_server_close() {
+    // the server silently closes the connection after each response
+    let (addr, accepted) = keep_alive_server(1);
+
+    let (communicator, (receiver, sender)) = ServerCommunicator::new().unwrap();
+    communicator.start();
+
+    for _ in 0..3 {
+        let id = sender.send(request(&addr)).unwrap();
+        let (response_id, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(response_id, id);
+        assert_eq!(response.unwrap().body, b"data");
+    }
+
+    assert_eq!(accepted.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn test_pool_idle_timeout() {
+    let (addr, _) = keep_alive_server(usize::MAX);
+
+    let pool = ConnectionPool::new(Duration::from_millis(50), 4);
+    let connection = pool.get(&addr).unwrap();
+    assert!(!connection.reused);
+    connection.keep_alive();
+
+    let connection = pool.get(&addr).unwrap();
+    assert!(connection.reused);
+    connection.keep_alive();
+    assert_eq!(pool.idle_count(&addr), 1);
+
+    std::thread::sleep(Duration::from_millis(100));
+    let connection = pool.get(&addr).unwrap();
+    assert!(!connection.reused);
+}
+
+#[test]
+fn test_pool_connection_limit() {
+    let (addr, accepted) = keep_alive_server(usize::MAX);
+
+    let pool = Arc::new(ConnectionPool::new(Duration::from_secs(30), 2));
+    let first = pool.get(&addr).unwrap();
+    let _second = pool.get(&addr).unwrap();
+    assert_eq!(pool.open_count(&addr), 2);
+
+    // the third connection waits until one of the others is returned
+    let waiting = {
+        let pool = pool.clone();
+        let addr = addr.clone();
+        std::thread::spawn(move || pool.get(&addr).unwrap().reused)
+    };
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(!waiting.is_finished());
+
+    first.keep_alive();
+    assert!(waiting.join().unwrap());
+    assert_eq!(accepted.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn test_parallel_workers() {
+    let (addr, accepted) = keep_alive_server(usize::MAX);
+
+    let config = CommunicatorConfig {
+        workers: 4,
+        max_connections_per_host: 2,
+        ..Default::default()
+    };
+    let (_, (receiver, sender)) = start_communicator(config);
+
+    let sent = (0..20)
+        .map(|_| sender.send(request(&addr)).unwrap())
+        .collect::<HashSet<_>>();
+    assert_eq!(sent.len(), 20);
+
+    // responses can come in any order, but each of them answers exactly one request
+    let mut received = HashSet::new();
+    for _ in 0..20 {
+        let (id, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(response.unwrap().body, b"data");
+        assert!(received.insert(id));
+    }
+    assert_eq!(received, sent);
+
+    assert!(accepted.load(Ordering::SeqCst) <= 2);
+}
+
+#[test]
+fn test_errors_are_delivered() {
+    // nobody listens on the address after the listener is dropped
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string();
+
+    let (communicator, (receiver, sender)) = ServerCommunicator::new().unwrap();
+    communicator.start();
+
+    let id = sender.send(request(&addr)).unwrap();
+    let (response_id, response) = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(response_id, id);
+    assert!(matches!(
+        response,
+        Err(ServerCommunicatorError::TcpError(err)) if err.kind() == std::io::ErrorKind::ConnectionRefused
+    ));
+
+    let mut without_host = request(&addr);
+    without_host.headers.clear();
+    let id = sender.send(without_host).unwrap();
+    let (response_id, response) = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(response_id, id);
+    assert!(matches!(
+        response,
+        Err(ServerCommunicatorError::NoHostNameinTheHeader)
+    ));
+}
+
+#[test]
+fn test_shutdown_drain() {
+    let (addr, _) = keep_alive_server(usize::MAX);
+    let (communicator, (receiver, sender)) = ServerCommunicator::new().unwrap();
+    let handle = communicator.start();
+
+    for _ in 0..10 {
+        sender.send(request(&addr)).unwrap();
+    }
+    handle.shutdown();
+
+    let stats = handle.join().unwrap();
+    assert_eq!(
+        stats,
+        CommunicatorStats {
+            completed: 10,
+            ..Default::default()
+        }
+    );
+    assert_eq!(
+        receiver
+            .try_iter()
+            .filter(|(_, response)| response.is_ok())
+            .count(),
+        10
+    );
+
+    // requests sent after the shutdown are not handled
+    assert!(sender.send(request(&addr)).is_err());
+}
+
+#[test]
+fn test_shutdown_cancel() {
+    // the server never answers
+    let (addr, requests) = status_server(vec![]);
+    let config = CommunicatorConfig {
+        timeouts: TimeoutConfig {
+            read: Some(Duration::from_secs(10)),
+            ..Default::default()
+        },
+        shutdown_policy: ShutdownPolicy::Cancel,
+        ..Default::default()
+    };
+    let (handle, (receiver, sender)) = start_communicator(config);
+
+    let ids = (0..3)
+        .map(|_| sender.send(request(&addr)).unwrap())
+        .collect::<Vec<_>>();
+    std::thread::sleep(Duration::from_millis(100));
+    let start = std::time::Instant::now();
+    handle.shutdown();
+
+    // requests sent after the shutdown are refused, before the workers stop too
+    assert!(matches!(
+        sender.send(request(&addr)),
+        Err(ServerCommunicatorError::Terminate)
+    ));
+
+    // the connection of the request in progress is closed, so nobody waits for the read timeout
+    let stats = handle.join().unwrap();
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(
+        stats,
+        CommunicatorStats {
+            cancelled: 3,
+            ..Default::default()
+        }
+    );
+    assert_eq!(requests.lock().unwrap().len(), 1);
+
+    let responses = receiver.try_iter().collect::<Vec<_>>();
+    assert_eq!(responses.len(), 3);
+    assert!(responses.iter().all(|(id, response)| {
+        if *id == ids[0] {
+            matches!(response, Err(ServerCommunicatorError::Cancelled))
+        } else {
+            matches!(response, Err(ServerCommunicatorError::Terminate))
+        }
+    }));
+}
+
+#[test]
+fn test_retry_status() {
+    let (addr, requests) = status_server(vec![503, 500, 200]);
+    let (_, (receiver, sender)) = start_communicator(CommunicatorConfig {
+        retry: RetryPolicy {
+            initial_backoff: Duration::from_millis(10),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+
+    sender.send(request(&addr)).unwrap();
+    let (_, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(response.unwrap().result, 200);
+    assert_eq!(requests.lock().unwrap().len(), 3);
+
+    // the last response is delivered, when all attempts are used
+    let (addr, requests) = status_server(vec![503, 503, 503, 200]);
+    sender.send(request(&addr)).unwrap();
+    let (_, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(response.unwrap().result, 503);
+    assert_eq!(requests.lock().unwrap().len(), 3);
+}
+
+#[test]
+fn test_read_timeout() {
+    let (addr, requests) = status_server(vec![]);
+    let (_, (receiver, sender)) = start_communicator(CommunicatorConfig {
+        retry: RetryPolicy {
+            max_attempts: 2,
+            initial_backoff: Duration::from_millis(10),
+            ..Default::default()
+        },
+        timeouts: TimeoutConfig {
+            read: Some(Duration::from_millis(100)),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+
+    sender.send(request(&addr)).unwrap();
+    let (_, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(matches!(
+        response,
+        Err(ServerCommunicatorError::TimeOutError(_))
+    ));
+    assert_eq!(requests.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn test_overall_deadline() {
+    let (addr, _) = status_server(vec![]);
+    let (_, (receiver, sender)) = start_communicator(CommunicatorConfig {
+        timeouts: TimeoutConfig {
+            overall: Some(Duration::from_millis(200)),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+
+    let start = std::time::Instant::now();
+    sender.send(request(&addr)).unwrap();
+    let (_, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(matches!(
+        response,
+        Err(ServerCommunicatorError::TimeOutError(_))
+    ));
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn test_overall_deadline_trickling_body() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        read_request(&mut reader);
+
+        // every read gets a byte long before the read timeout
+        let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\n");
+        for _ in 0..100 {
+            if stream.write_all(b"x").is_err() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    });
+
+    let (_, (receiver, sender)) = start_communicator(CommunicatorConfig {
+        retry: RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        },
+        timeouts: TimeoutConfig {
+            read: Some(Duration::from_secs(1)),
+            overall: Some(Duration::from_millis(300)),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+
+    let start = std::time::Instant::now();
+    sender.send(request(&addr)).unwrap();
+    let (_, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(matches!(
+        response,
+        Err(ServerCommunicatorError::TimeOutError(_))
+    ));
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn test_backoff() {
+    let policy = RetryPolicy {
+        initial_backoff: Duration::from_millis(100),
+        max_backoff: Duration::from_millis(300),
+        jitter: 0.0,
+        ..Default::default()
+    };
+    assert_eq!(policy.backoff(1), Duration::from_millis(100));
+    assert_eq!(policy.backoff(2), Duration::from_millis(200));
+    assert_eq!(policy.backoff(3), Duration::from_millis(300));
+    assert_eq!(policy.backoff(100), Duration::from_millis(300));
+
+    let policy = RetryPolicy {
+        jitter: 0.5,
+        ..policy
+    };
+    for _ in 0..100 {
+        let backoff = policy.backoff(2);
+        assert!(backoff >= Duration::from_millis(100) && backoff <= Duration::from_millis(200));
+    }
+
+    // the delay stays between zero and the maximum
+    let negative = RetryPolicy {
+        multiplier: -2.0,
+        jitter: 0.0,
+        ..policy.clone()
+    };
+    assert_eq!(negative.backoff(1), Duration::from_millis(100));
+    assert_eq!(negative.backoff(2), Duration::ZERO);
+    assert_eq!(negative.backoff(3), Duration::from_millis(300));
+    for multiplier in [f64::INFINITY, f64::NEG_INFINITY, f64::NAN] {
+        let policy = RetryPolicy {
+            multiplier,
+            ..policy.clone()
+        };
+        assert!(policy.backoff(2) <= Duration::from_millis(300));
+    }
+
+    let mut post = HttpRequest::new(
+        HttpRequestMethod::POST,
+        http_message::http_messages::path::Path::default(),
+        "HTTP/1.1",
+    );
+    post.add_header("Host", "localhost");
+    let unavailable = Ok(HttpResponse::new(503, "Service Unavailable", "HTTP/1.1"));
+    assert!(policy.should_retry(&request("localhost"), &unavailable));
+    assert!(!policy.should_retry(&post, &unavailable));
+    assert!(!policy.should_retry(
+        &request("localhost"),
+        &Err(ServerCommunicatorError::NoHostNameinTheHeader)
+    ));
+}
+
+#[test]
+fn test_loopback_transport() {
+    let (transport, listener) = LoopbackTransport::new();
+    let accepted = Arc::new(AtomicUsize::new(0));
+
+    let counter = accepted.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            counter.fetch_add(1, Ordering::SeqCst);
+            std::thread::spawn(move || answer_requests(stream, usize::MAX));
+        }
+    });
+
+    let config = CommunicatorConfig {
+        transport: Arc::new(transport),
+        ..Default::default()
+    };
+    let (_, (receiver, sender)) = start_communicator(config);
+
+    for _ in 0..3 {
+        let id = sender.send(request("fake.host")).unwrap();
+        let (response_id, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(response_id, id);
+        assert_eq!(response.unwrap().body, b"data");
+    }
+
+    // the in-memory connection is kept alive as well
+    assert_eq!(accepted.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_loopback_stream() {
+    let (mut client, mut server) = LoopbackStream::pair();
+
+    client.write_all(b"ping").unwrap();
+    let mut buf = [0; 4];
+    server.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"ping");
+
+    server
+        .set_read_timeout(Some(Duration::from_millis(10)))
+        .unwrap();
+    assert_eq!(
+        server.read(&mut buf).unwrap_err().kind(),
+        std::io::ErrorKind::WouldBlock
+    );
+
+    assert!(!server.is_closed());
+    drop(client);
+    assert!(server.is_closed());
+    assert_eq!(server.read(&mut buf).unwrap(), 0);
+    assert_eq!(
+        server.write(b"pong").unwrap_err().kind(),
+        std::io::ErrorKind::BrokenPipe
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unix_transport() {
+    use std::os::unix::net::UnixListener;
+
+    let path =
+        std::env::temp_dir().join(format!("server_communicator_{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { break };
+            std::thread::spawn(move || answer_requests(stream, usize::MAX));
+        }
+    });
+
+    let config = CommunicatorConfig {
+        transport: Arc::new(UnixTransport::new(&path)),
+        ..Default::default()
+    };
+    let (_, (receiver, sender)) = start_communicator(config);
+
+    sender.send(request("localhost")).unwrap();
+    let (_, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(response.unwrap().body, b"data");
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_fault_fail_connect() {
+    let (addr, accepted) = keep_alive_server(usize::MAX);
+    let profile = FaultProfile {
+        fail_connect: 1.0,
+        ..Default::default()
+    };
+    let (_, (receiver, sender)) = start_communicator(CommunicatorConfig {
+        transport: Arc::new(FaultyTransport::new(
+            Arc::new(TcpTransport::default()),
+            profile,
+        )),
+        ..Default::default()
+    });
+
+    sender.send(request(&addr)).unwrap();
+    let (_, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(matches!(
+        response,
+        Err(ServerCommunicatorError::TcpError(err)) if err.kind() == std::io::ErrorKind::ConnectionRefused
+    ));
+    assert_eq!(accepted.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn test_fault_drop_is_retried() {
+    let (addr, accepted) = keep_alive_server(usize::MAX);
+    let profile = FaultProfile {
+        drop_connection: 1.0,
+        cut_after: 0..1,
+        ..Default::default()
+    };
+    let config = CommunicatorConfig {
+        retry: RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (handle, (receiver, sender)) = start_communicator(CommunicatorConfig {
+        transport: Arc::new(FaultyTransport::new(
+            Arc::new(TcpTransport::default()),
+            profile,
+        )),
+        ..config
+    });
+
+    sender.send(request(&addr)).unwrap();
+    let (_, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(matches!(
+        response,
+        Err(ServerCommunicatorError::TcpError(err)) if err.kind() == std::io::ErrorKind::ConnectionReset
+    ));
+    assert_eq!(handle.stats().retries, 2);
+    assert_eq!(accepted.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn test_fault_seed_is_repeatable() {
+    let run = |seed| {
+        let (addr, _) = keep_alive_server(usize::MAX);
+        let profile = FaultProfile {
+            seed,
+            truncate: 0.5,
+            drop_connection: 0.2,
+            cut_after: 20..60,
+            corrupt: 0.2,
+            ..Default::default()
+        };
+        let config = CommunicatorConfig {
+            retry: RetryPolicy::none(),
+            ..Default::default()
+        };
+        let (_, (receiver, sender)) = start_communicator(CommunicatorConfig {
+            transport: Arc::new(FaultyTransport::new(
+                Arc::new(TcpTransport::default()),
+                profile,
+            )),
+            ..config
+        });
+
+        (0..20)
+            .map(|_| {
+                sender.send(request(&addr)).unwrap();
+                match receiver.recv_timeout(Duration::from_secs(5)).unwrap().1 {
+                    Ok(response) => format!("{} {:?}", response.truncated, response.body),
+                    Err(err) => format!("{}", err),
+                }
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let outcomes = run(42);
+    assert_eq!(outcomes, run(42));
+    assert!(
+        outcomes
+            .iter()
+            .any(|outcome| outcome == "false [100, 97, 116, 97]")
+    );
+    assert!(
+        outcomes
+            .iter()
+            .any(|outcome| outcome != "false [100, 97, 116, 97]")
+    );
+}
+
+#[test]
+fn test_fault_reorder() {
+    let (addr, _) = keep_alive_server(usize::MAX);
+    // with this seed only the first connection is held back
+    let profile = FaultProfile {
+        seed: 28,
+        reorder: 0.5,
+        max_delay: Duration::from_millis(300),
+        ..Default::default()
+    };
+    let config = CommunicatorConfig {
+        workers: 2,
+        ..Default::default()
+    };
+    let (_, (receiver, sender)) = start_communicator(CommunicatorConfig {
+        transport: Arc::new(FaultyTransport::new(
+            Arc::new(TcpTransport::default()),
+            profile,
+        )),
+        ..config
+    });
+
+    let first = sender.send(request(&addr)).unwrap();
+    std::thread::sleep(Duration::from_millis(50));
+    let second = sender.send(request(&addr)).unwrap();
+
+    // the first response is held back until the second one is received
+    let received = (0..2)
+        .map(|_| receiver.recv_timeout(Duration::from_secs(5)).unwrap().0)
+        .collect::<Vec<_>>();
+    assert_eq!(received, vec![second, first]);
+}
+
+#[test]
+fn test_record_and_replay() {
+    let path = std::env::temp_dir().join(format!("recording_{}.txt", std::process::id()));
+
+    // the server closes every second connection after the response
+    let (addr, _) = keep_alive_server(2);
+    let config = CommunicatorConfig {
+        record: Some(path.clone()),
+        ..Default::default()
+    };
+    let (handle, (receiver, sender)) = start_communicator(config);
+
+    let mut recorded = vec![];
+    for _ in 0..3 {
+        sender.send(request(&addr)).unwrap();
+        let (_, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        recorded.push(response.unwrap());
+    }
+    drop(sender);
+    handle.join().unwrap();
+
+    let exchanges = read_recording(&path).unwrap();
+    assert_eq!(exchanges.len(), 3);
+    assert_eq!(
+        exchanges
+            .iter()
+            .map(|exchange| exchange.connection)
+            .collect::<Vec<_>>(),
+        vec![0, 0, 1]
+    );
+    assert!(
+        exchanges
+            .iter()
+            .all(|exchange| exchange.response.ends_with(b"data"))
+    );
+
+    let replay = ReplayTransport::load(&path)
+        .unwrap()
+        .with_recorded_speed(true);
+    let config = CommunicatorConfig {
+        transport: Arc::new(replay.clone()),
+        ..Default::default()
+    };
+    let (_, (receiver, sender)) = start_communicator(config);
+
+    for expected in recorded {
+        sender.send(request(&addr)).unwrap();
+        let (_, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        let response = response.unwrap();
+        assert_eq!(
+            (response.result, response.body, response.truncated),
+            (expected.result, expected.body, expected.truncated)
+        );
+    }
+    assert_eq!(replay.remaining(), 0);
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_replay_request_body() {
+    let mut post = redirect_request("replay.host", "/upload");
+    post.method = HttpRequestMethod::POST;
+    post.add_header("Content-Length", "5");
+    post.body = b"hello".to_vec();
+
+    let mut chunked = redirect_request("replay.host", "/upload");
+    chunked.method = HttpRequestMethod::POST;
+    chunked.add_header("Transfer-Encoding", "chunked");
+    chunked.body = b"4;ext=1\r\nWiki\r\n0\r\nTrailer: 1\r\n\r\n".to_vec();
+
+    let get = request("replay.host");
+
+    // all requests are sent on one connection, each body must be read before the next request
+    let exchanges = [(&post, "created"), (&chunked, "chunked"), (&get, "data")]
+        .into_iter()
+        .map(|(request, body)| {
+            let mut bytes = vec![];
+            request.serialize_into(&mut bytes).unwrap();
+            Exchange {
+                request: bytes,
+                response: format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .into_bytes(),
+                ..Default::default()
+            }
+        })
+        .collect();
+    let replay = ReplayTransport::new(exchanges);
+    let config = CommunicatorConfig {
+        transport: Arc::new(replay.clone()),
+        retry: RetryPolicy::none(),
+        ..Default::default()
+    };
+    let (handle, (receiver, sender)) = start_communicator(config);
+
+    for (request, expected) in [(post, "created"), (chunked, "chunked"), (get, "data")] {
+        sender.send(request).unwrap();
+        let (_, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(response.unwrap().body, expected.as_bytes());
+    }
+    assert_eq!(replay.remaining(), 0);
+    assert_eq!(handle.metrics().connect.count, 1);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_record_error() {
+    let (addr, _) = keep_alive_server(usize::MAX);
+    // every write into the file fails
+    let config = CommunicatorConfig {
+        record: Some("/dev/full".into()),
+        retry: RetryPolicy::none(),
+        ..Default::default()
+    };
+    let (_, (receiver, sender)) = start_communicator(config);
+
+    // the first exchange is written, when the next request starts
+    sender.send(request(&addr)).unwrap();
+    let (_, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(response.unwrap().body, b"data");
+
+    for _ in 0..2 {
+        sender.send(request(&addr)).unwrap();
+        let (_, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(
+            response,
+            Err(ServerCommunicatorError::TcpError(err)) if err.to_string().contains("could not be recorded")
+        ));
+    }
+}
+
+/// Server which reads all requests sent back to back, before it answers them.
+/// Each connection is closed after `answers_per_connection` responses, the sizes of the received batches are returned
+fn pipeline_server(answers_per_connection: usize) -> (String, Arc<Mutex<Vec<usize>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let batches = Arc::new(Mutex::new(vec![]));
+
+    let received = batches.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { break };
+            let received = received.clone();
+
+            std::thread::spawn(move || {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut answered = 0;
+                loop {
+                    // the requests of one batch come without a pause
+                    let mut batch = 0;
+                    stream.set_read_timeout(None).unwrap();
+                    loop {
+                        let mut line = String::new();
+                        match reader.read_line(&mut line) {
+                            Ok(0) => return,
+                            Ok(_) if line == "\r\n" => {
+                                batch += 1;
+                                stream
+                                    .set_read_timeout(Some(Duration::from_millis(200)))
+                                    .unwrap();
+                            }
+                            Ok(_) => {}
+                            Err(_) if batch > 0 => break,
+                            Err(_) => return,
+                        }
+                    }
+                    received.lock().unwrap().push(batch);
+
+                    for _ in 0..batch {
+                        if answered == answers_per_connection {
+                            return;
+                        }
+                        answered += 1;
+                        let _ = stream.write_all(DATA_RESPONSE.as_bytes());
+                    }
+                }
+            });
+        }
+    });
+
+    (addr, batches)
+}
+
+#[test]
+fn test_pipelining() {
+    let (addr, batches) = pipeline_server(usize::MAX);
+    let (communicator, (receiver, sender)) = ServerCommunicator::with_config(CommunicatorConfig {
+        pipeline_depth: 4,
+        ..Default::default()
+    })
+    .unwrap();
+
+    // the requests are queued before the worker starts, so they are sent in two full batches
+    let sent = (0..8)
+        .map(|_| sender.send(request(&addr)).unwrap())
+        .collect::<Vec<_>>();
+    communicator.start();
+
+    let received = (0..8)
+        .map(|_| {
+            let (id, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert_eq!(response.unwrap().body, b"data");
+            id
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(received, sent);
+    assert_eq!(*batches.lock().unwrap(), vec![4, 4]);
+}
+
+#[test]
+fn test_pipeline_reissue() {
+    // the server closes the connection after two responses of the batch
+    let (addr, batches) = pipeline_server(2);
+    let (communicator, (receiver, sender)) = ServerCommunicator::with_config(CommunicatorConfig {
+        pipeline_depth: 4,
+        ..Default::default()
+    })
+    .unwrap();
+
+    let sent = (0..4)
+        .map(|_| sender.send(request(&addr)).unwrap())
+        .collect::<Vec<_>>();
+    communicator.start();
+
+    let received = (0..4)
+        .map(|_| {
+            let (id, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert_eq!(response.unwrap().body, b"data");
+            id
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(received, sent);
+    // the unanswered requests are sent again one by one
+    assert_eq!(*batches.lock().unwrap(), vec![4, 1, 1]);
+}
+
+#[test]
+fn test_pipeline_retry_budget() {
+    for max_attempts in [1, 2] {
+        let (addr, requests) = route_server(|path| match path {
+            "/busy" => "HTTP/1.1 503 Busy\r\nContent-Length: 4\r\n\r\nbusy".to_string(),
+            _ => DATA_RESPONSE.to_string(),
+        });
+        let mut config = CommunicatorConfig {
+            pipeline_depth: 4,
+            ..Default::default()
+        };
+        config.retry.max_attempts = max_attempts;
+        config.retry.initial_backoff = Duration::from_millis(10);
+        let (communicator, (receiver, sender)) = ServerCommunicator::with_config(config).unwrap();
+
+        let busy = sender.send(redirect_request(&addr, "/busy")).unwrap();
+        for _ in 0..3 {
+            sender.send(redirect_request(&addr, "/ok")).unwrap();
+        }
+        drop(sender);
+        let (_, metrics) = communicator.start().join_with_metrics().unwrap();
+
+        let responses: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(responses.len(), 4);
+        for (id, response) in responses {
+            let status = if id == busy { 503 } else { 200 };
+            assert_eq!(response.unwrap().result, status);
+        }
+        // the pipelined attempt is the first one of the retry policy
+        let sent = requests.lock().unwrap().len();
+        assert_eq!(sent, 3 + max_attempts);
+        // the responses to the requests after the first one in the batch do not show the time to the first byte
+        assert_eq!(metrics.first_byte.count, max_attempts);
+    }
+}
+
+#[test]
+fn test_no_pipelining_while_recording() {
+    let path = std::env::temp_dir().join(format!("pipelined_{}.txt", std::process::id()));
+    let (addr, batches) = pipeline_server(usize::MAX);
+    let (communicator, (receiver, sender)) = ServerCommunicator::with_config(CommunicatorConfig {
+        pipeline_depth: 4,
+        record: Some(path.clone()),
+        ..Default::default()
+    })
+    .unwrap();
+
+    for _ in 0..3 {
+        sender.send(request(&addr)).unwrap();
+    }
+    drop(sender);
+    communicator.start().join().unwrap();
+    assert_eq!(receiver.try_iter().count(), 3);
+
+    // each request is recorded as its own exchange
+    assert_eq!(*batches.lock().unwrap(), vec![1, 1, 1]);
+    assert_eq!(read_recording(&path).unwrap().len(), 3);
+
+    let _ = std::fs::remove_file(&path);
+}
+
+/// Sends `requests` requests with the config and returns the time until all of them are answered
+fn time_requests(addr: &str, config: CommunicatorConfig, requests: usize) -> Duration {
+    let start = std::time::Instant::now();
+    let (_, (receiver, sender)) = start_communicator(config);
+
+    for _ in 0..requests {
+        sender.send(request(addr)).unwrap();
+    }
+    for _ in 0..requests {
+        let (_, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(response.unwrap().body, b"data");
+    }
+    start.elapsed()
+}
+
+/// Limiter with the rate limit, the start of its buckets is returned too
+fn rate_limiter(rate_limit: RateLimit) -> (limit::Limiter, std::time::Instant) {
+    let limiter = limit::Limiter::new(&CommunicatorConfig {
+        rate_limit,
+        ..Default::default()
+    });
+    (limiter, std::time::Instant::now())
+}
+
+#[test]
+fn test_requests_rate_limit() {
+    let (limiter, start) = rate_limiter(RateLimit {
+        requests_per_sec: Some(5.0),
+        bytes_per_sec: None,
+    });
+
+    // the first 5 requests are sent at once, the rest one every 200ms
+    for _ in 0..5 {
+        assert_eq!(limiter.request_at(start), Ok(()));
+    }
+    assert_eq!(limiter.request_at(start), Err(Duration::from_millis(200)));
+    let later = start + Duration::from_millis(100);
+    assert_eq!(limiter.request_at(later), Err(Duration::from_millis(100)));
+
+    let next = start + Duration::from_millis(200);
+    assert_eq!(limiter.request_at(next), Ok(()));
+    assert!(limiter.request_at(next).is_err());
+
+    // the tokens do not pile up over a second worth of requests
+    let idle = start + Duration::from_secs(10);
+    let sent = (0..10)
+        .take_while(|_| limiter.request_at(idle).is_ok())
+        .count();
+    assert_eq!(sent, 5);
+}
+
+#[test]
+fn test_bytes_rate_limit() {
+    let (limiter, start) = rate_limiter(RateLimit {
+        requests_per_sec: None,
+        bytes_per_sec: Some(16.0),
+    });
+
+    // requests are sent until the bytes received before are paid off
+    assert_eq!(limiter.request_at(start), Ok(()));
+    limiter.received_at(16, start);
+    assert_eq!(limiter.request_at(start), Ok(()));
+    limiter.received_at(4, start);
+    assert_eq!(limiter.request_at(start), Err(Duration::from_millis(250)));
+
+    let paid = start + Duration::from_millis(250);
+    assert_eq!(limiter.request_at(paid), Ok(()));
+    assert_eq!(limiter.request_at(paid), Ok(()));
+}
+
+#[test]
+fn test_rate_limits_together() {
+    let (limiter, start) = rate_limiter(RateLimit {
+        requests_per_sec: Some(1.0),
+        bytes_per_sec: Some(10.0),
+    });
+
+    assert_eq!(limiter.request_at(start), Ok(()));
+    limiter.received_at(30, start);
+
+    // the request waits for the bytes, which take longer than its token
+    assert_eq!(limiter.request_at(start), Err(Duration::from_secs(2)));
+    let token = start + Duration::from_secs(1);
+    assert_eq!(limiter.request_at(token), Err(Duration::from_secs(1)));
+
+    // no token is taken while the request waits for the bytes
+    let paid = start + Duration::from_secs(2);
+    assert_eq!(limiter.request_at(paid), Ok(()));
+}
+
+#[test]
+fn test_in_flight_limit() {
+    let limiter = limit::Limiter::new(&CommunicatorConfig {
+        max_in_flight: Some(2),
+        ..Default::default()
+    });
+    assert_eq!(limiter.max_in_flight(), Some(2));
+
+    // the permits are held at once
+    let first = limiter.start(1);
+    let second = limiter.start(1);
+    drop((first, second));
+    drop(limiter.start(2));
+
+    let in_flight = AtomicUsize::new(0);
+    let most = AtomicUsize::new(0);
+    std::thread::scope(|scope| {
+        for _ in 0..8 {
+            scope.spawn(|| {
+                for _ in 0..100 {
+                    let _permit = limiter.start(1);
+                    let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    most.fetch_max(now, Ordering::SeqCst);
+                    std::thread::yield_now();
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                }
+            });
+        }
+    });
+    assert!(most.load(Ordering::SeqCst) <= 2);
+    assert_eq!(in_flight.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn test_max_in_flight() {
+    let (addr, accepted) = keep_alive_server(usize::MAX);
+
+    let config = CommunicatorConfig {
+        workers: 4,
+        max_in_flight: Some(1),
+        ..Default::default()
+    };
+    time_requests(&addr, config, 20);
+
+    // only one request is handled at a time, so one connection is enough
+    assert_eq!(accepted.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_queue_full() {
+    let (addr, _) = keep_alive_server(usize::MAX);
+
+    let config = CommunicatorConfig {
+        queue_capacity: Some(2),
+        ..Default::default()
+    };
+    let (communicator, (receiver, sender)) = ServerCommunicator::with_config(config).unwrap();
+
+    sender.try_send(request(&addr)).unwrap();
+    sender.try_send(request(&addr)).unwrap();
+    assert!(matches!(
+        sender.try_send(request(&addr)),
+        Err(ServerCommunicatorError::QueueFull)
+    ));
+
+    // a blocking send waits until the worker takes a request from the queue
+    let blocked = sender.clone();
+    let request = request(&addr);
+    let sending = std::thread::spawn(move || blocked.send(request).unwrap());
+    communicator.start();
+    sending.join().unwrap();
+
+    for _ in 0..3 {
+        let (_, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(response.unwrap().body, b"data");
+    }
+}
+
+#[test]
+fn test_queue_capacity_includes_scheduled() {
+    // the server never answers, so the worker keeps its first request
+    let (addr, _) = status_server(vec![]);
+    let config = CommunicatorConfig {
+        workers: 1,
+        queue_capacity: Some(2),
+        shutdown_policy: ShutdownPolicy::Cancel,
+        ..Default::default()
+    };
+    let (communicator, (_receiver, sender)) = ServerCommunicator::with_config(config).unwrap();
+
+    sender.try_send(request(&addr)).unwrap();
+    sender.try_send(request(&addr)).unwrap();
+    let blocked = sender.clone();
+    let third = request(&addr);
+    let sending = std::thread::spawn(move || blocked.send(third).unwrap());
+    let handle = communicator.start();
+    sending.join().unwrap();
+
+    // the worker handles one request, the scheduler took the other ones out of the channel, they still fill the queue
+    assert!(matches!(
+        sender.try_send(request(&addr)),
+        Err(ServerCommunicatorError::QueueFull)
+    ));
+
+    handle.shutdown();
+    handle.join().unwrap();
+}
+
+#[test]
+fn test_histogram() {
+    let mut histogram = Histogram::default();
+    assert_eq!(histogram.quantile(0.5), None);
+
+    [1, 3, 3, 40, 6998]
+        .into_iter()
+        .for_each(|millis| histogram.record(Duration::from_millis(millis)));
+
+    assert_eq!(histogram.count, 5);
+    assert_eq!(histogram.min, Some(Duration::from_millis(1)));
+    assert_eq!(histogram.mean(), Some(Duration::from_millis(1409)));
+    assert_eq!(histogram.quantile(0.5), Some(Duration::from_millis(5)));
+    assert_eq!(histogram.quantile(0.8), Some(Duration::from_millis(50)));
+    // values above all bounds are estimated with the maximum
+    assert_eq!(histogram.quantile(1.0), Some(Duration::from_millis(6998)));
+}
+
+#[test]
+fn test_metrics() {
+    let (addr, _) = keep_alive_server(usize::MAX);
+    let (communicator, (receiver, sender)) = ServerCommunicator::new().unwrap();
+    let handle = communicator.start();
+
+    for _ in 0..5 {
+        sender.send(request(&addr)).unwrap();
+        receiver
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap()
+            .1
+            .unwrap();
+    }
+
+    // nobody listens on the address after the listener is dropped
+    let refused = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string();
+    let config = CommunicatorConfig {
+        retry: RetryPolicy {
+            max_attempts: 2,
+            retryable_error: |_| true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (failing, (failed, failing_sender)) = start_communicator(config);
+    failing_sender.send(request(&refused)).unwrap();
+    assert!(
+        failed
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap()
+            .1
+            .is_err()
+    );
+
+    drop(sender);
+    let (_, metrics) = handle.join_with_metrics().unwrap();
+    // the connection is kept alive, so it is established only once
+    assert_eq!(metrics.connect.count, 1);
+    assert_eq!(metrics.first_byte.count, 5);
+    assert_eq!(metrics.total.count, 5);
+    assert_eq!(metrics.bytes_received, 20);
+    assert_eq!(metrics.truncated, 0);
+    assert!(metrics.errors.is_empty());
+
+    let metrics = failing.metrics();
+    assert_eq!(metrics.connect.count, 0);
+    assert_eq!(metrics.total.count, 1);
+    assert_eq!(metrics.retries, 1);
+    assert_eq!(
+        metrics.errors.into_iter().collect::<Vec<_>>(),
+        vec![("tcp: connection refused".to_string(), 2)]
+    );
+}
+
+fn redirect_to(location: &str) -> String {
+    format!(
+        "HTTP/1.1 301 Moved Permanently\r\nLocation: {}\r\nContent-Length: 0\r\n\r\n",
+        location
+    )
+}
+
+fn redirect_request(addr: &str, path: &str) -> HttpRequest {
+    let mut request = request(addr);
+    request.request_target = path
+        .parse::<http_message::http_messages::path::Path>()
+        .unwrap()
+        .into();
+    request
+}
+
+#[test]
+fn test_redirect_keeps_range() {
+    let (target, target_requests) = route_server(|_| {
+        "HTTP/1.1 206 Partial Content\r\nContent-Length: 4\r\n\r\ndata".to_string()
+    });
+    let location = format!("http://{}/files/data", target);
+    let (origin, origin_requests) = route_server(move |path| match path {
+        "/old/data" => redirect_to("moved"),
+        _ => redirect_to(&location),
+    });
+
+    let (_, (receiver, sender)) = start_communicator(CommunicatorConfig {
+        ..Default::default()
+    });
+    let mut request = redirect_request(&origin, "/old/data");
+    request.add_header("Range", "bytes=0-3");
+    sender.send(request).unwrap();
+
+    let (_, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(response.unwrap().body, b"data");
+
+    // the relative location is resolved against the directory of the request, the absolute one leads to the other host
+    let range = Some("bytes=0-3".to_string());
+    assert_eq!(
+        *origin_requests.lock().unwrap(),
+        vec![
+            ("/old/data".to_string(), range.clone()),
+            ("/old/moved".to_string(), range.clone())
+        ]
+    );
+    assert_eq!(
+        *target_requests.lock().unwrap(),
+        vec![("/files/data".to_string(), range)]
+    );
+}
+
+#[test]
+fn test_redirect_loop() {
+    let (addr, _) = route_server(|path| match path {
+        "/a" => redirect_to("/b"),
+        _ => redirect_to("/a"),
+    });
+
+    let (_, (receiver, sender)) = start_communicator(CommunicatorConfig {
+        ..Default::default()
+    });
+    sender.send(redirect_request(&addr, "/a")).unwrap();
+
+    let (_, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(matches!(
+        response,
+        Err(ServerCommunicatorError::RedirectLoop(target)) if target.ends_with("/a")
+    ));
+}
+
+#[test]
+fn test_redirect_hop_limit() {
+    let (addr, _) = route_server(|path| {
+        let hop = path[1..].parse::<usize>().unwrap();
+        redirect_to(&format!("/{}", hop + 1))
+    });
+
+    let (_, (receiver, sender)) = start_communicator(CommunicatorConfig {
+        redirect: RedirectPolicy { max_hops: 2 },
+        ..Default::default()
+    });
+    sender.send(redirect_request(&addr, "/0")).unwrap();
+    let (_, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(matches!(
+        response,
+        Err(ServerCommunicatorError::TooManyRedirects(2))
+    ));
+
+    // without following, the redirect is the response
+    let (_, (receiver, sender)) = start_communicator(CommunicatorConfig {
+        redirect: RedirectPolicy::none(),
+        ..Default::default()
+    });
+    sender.send(redirect_request(&addr, "/0")).unwrap();
+    let (_, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(response.unwrap().result, 301);
+}
+
+#[test]
+fn test_redirect_resolution() {
+    let redirect = |location: &str| {
+        let mut response = HttpResponse::new(302, "Found", "HTTP/1.1");
+        response.add_header("Location", location);
+        response
+    };
+    let mut request = redirect_request("a.host", "/dir/file?x=1");
+    request.add_header("Authorization", "Bearer secret");
+    request.add_header("Cookie", "session=1");
+    request.add_header("Proxy-Authorization", "Basic cHJveHk=");
+
+    // a query-only reference keeps the current path (RFC 3986 5.2.2)
+    let next = redirect::redirected(&request, &redirect("?q=1")).unwrap();
+    assert_eq!(next.request_target.to_string(), "/dir/file?q=1");
+    let next = redirect::redirected(&request, &redirect("")).unwrap();
+    assert_eq!(next.request_target.to_string(), "/dir/file?x=1");
+
+    // only a scheme at the start makes the location absolute
+    let next = redirect::redirected(&request, &redirect("next?u=http://x")).unwrap();
+    assert_eq!(next.request_target.to_string(), "/dir/next?u=http://x");
+    assert_eq!(next.headers.get(&"Host".into()).unwrap().value, "a.host");
+    let err = redirect::redirected(&request, &redirect("HTTP+x.1://b.host/")).unwrap_err();
+    assert!(err.to_string().contains("scheme"), "{}", err);
+
+    // credentials stay with the same host
+    let next = redirect::redirected(&request, &redirect("http://A.host/other")).unwrap();
+    assert_eq!(next.request_target.to_string(), "/other");
+    assert!(next.headers.contains_key(&"Authorization".into()));
+
+    let next = redirect::redirected(&request, &redirect("http://b.host/other")).unwrap();
+    assert_eq!(next.headers.get(&"Host".into()).unwrap().value, "b.host");
+    for header in ["Authorization", "Cookie", "Proxy-Authorization"] {
+        assert!(!next.headers.contains_key(&header.into()), "{}", header);
+    }
+}
+
+/// Proxy stub, which answers the forwarded requests itself with the body "data" and tunnels CONNECT requests to the origin.
+/// With `reject` CONNECT is answered with 407. Returns its address and the received request lines
+fn proxy_stub(reject: bool) -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let lines = Arc::new(Mutex::new(vec![]));
+
+    let received = lines.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { break };
+            let received = received.clone();
+
+            std::thread::spawn(move || {
+                let mut reader = BufReader::new(stream);
+                loop {
+                    let mut request_line = String::new();
+                    if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+                        return;
+                    }
+                    if !read_request(&mut reader) {
+                        return;
+                    }
+                    received
+                        .lock()
+                        .unwrap()
+                        .push(request_line.trim().to_string());
+
+                    let Some(target) = request_line.strip_prefix("CONNECT ") else {
+                        let _ = reader.get_mut().write_all(DATA_RESPONSE.as_bytes());
+                        continue;
+                    };
+
+                    let mut client = reader.into_inner();
+                    if reject {
+                        let _ = client.write_all(
+                            b"HTTP/1.1 407 Proxy Authentication Required\r\nContent-Length: 0\r\n\r\n",
+                        );
+                        return;
+                    }
+
+                    let target = target.split(' ').next().unwrap();
+                    let mut origin = std::net::TcpStream::connect(target).unwrap();
+                    client
+                        .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                        .unwrap();
+
+                    let (mut from_client, mut to_origin) =
+                        (client.try_clone().unwrap(), origin.try_clone().unwrap());
+                    std::thread::spawn(move || std::io::copy(&mut from_client, &mut to_origin));
+                    let _ = std::io::copy(&mut origin, &mut client);
+                    return;
+                }
+            });
+        }
+    });
+
+    (addr, lines)
+}
+
+#[test]
+fn test_proxy_forward() {
+    let (proxy, lines) = proxy_stub(false);
+    let (_, (receiver, sender)) = start_communicator(CommunicatorConfig {
+        proxy: Some(ProxyConfig::new(&format!("http://{}/", proxy))),
+        retry: RetryPolicy::none(),
+        ..Default::default()
+    });
+
+    // the origin does not exist, the proxy answers by itself
+    for _ in 0..2 {
+        sender.send(request("origin.test:8080")).unwrap();
+        let (_, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(response.unwrap().body, b"data");
+    }
+
+    assert_eq!(
+        *lines.lock().unwrap(),
+        vec!["GET http://origin.test:8080/ HTTP/1.1"; 2]
+    );
+}
+
+#[test]
+fn test_proxy_tunnel() {
+    let (origin, accepted) = keep_alive_server(usize::MAX);
+    let (proxy, lines) = proxy_stub(false);
+    let (_, (receiver, sender)) = start_communicator(CommunicatorConfig {
+        proxy: Some(ProxyConfig::new(&proxy).with_mode(ProxyMode::Tunnel)),
+        retry: RetryPolicy::none(),
+        ..Default::default()
+    });
+
+    for _ in 0..2 {
+        sender.send(request(&origin)).unwrap();
+        let (_, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(response.unwrap().body, b"data");
+    }
+
+    // the tunnel is kept alive like a direct connection
+    assert_eq!(
+        *lines.lock().unwrap(),
+        vec![format!("CONNECT {} HTTP/1.1", origin)]
+    );
+    assert_eq!(accepted.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_proxy_errors() {
+    let (origin, _) = keep_alive_server(usize::MAX);
+    let (proxy, _) = proxy_stub(true);
+    let (_, (receiver, sender)) = start_communicator(CommunicatorConfig {
+        proxy: Some(ProxyConfig::new(&proxy).with_mode(ProxyMode::Tunnel)),
+        retry: RetryPolicy::none(),
+        ..Default::default()
+    });
+
+    sender.send(request(&origin)).unwrap();
+    let (_, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(matches!(
+        response,
+        Err(ServerCommunicatorError::ProxyError(err)) if err.kind() == std::io::ErrorKind::PermissionDenied
+    ));
+
+    // nobody listens on the address after the listener is dropped
+    let unreachable = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string();
+    let (_, (receiver, sender)) = start_communicator(CommunicatorConfig {
+        proxy: Some(ProxyConfig::new(&unreachable)),
+        retry: RetryPolicy::none(),
+        ..Default::default()
+    });
+    sender.send(request(&origin)).unwrap();
+    let (_, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(matches!(
+        response,
+        Err(ServerCommunicatorError::ProxyError(err)) if err.kind() == std::io::ErrorKind::ConnectionRefused
+    ));
+}
+
+#[test]
+fn test_no_proxy() {
+    let (origin, accepted) = keep_alive_server(usize::MAX);
+    let (proxy, lines) = proxy_stub(false);
+    let config = ProxyConfig::new(&proxy).with_no_proxy("example.com, 127.0.0.1");
+
+    assert!(config.is_used_for("origin.test:80"));
+    assert!(!config.is_used_for("www.example.com"));
+    assert!(config.is_used_for("notexample.com"));
+
+    let (_, (receiver, sender)) = start_communicator(CommunicatorConfig {
+        proxy: Some(config),
+        retry: RetryPolicy::none(),
+        ..Default::default()
+    });
+    sender.send(request(&origin)).unwrap();
+    let (_, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(response.unwrap().body, b"data");
+
+    assert!(lines.lock().unwrap().is_empty());
+    assert_eq!(accepted.load(Ordering::SeqCst), 1);
+}
+
+/// all events of the streamed request, until its end
+fn stream_events(events: &Receiver<BodyEvent>) -> Vec<BodyEvent> {
+    let mut received = vec![];
+    while !matches!(received.last(), Some(BodyEvent::End { .. })) {
+        received.push(events.recv_timeout(Duration::from_secs(5)).unwrap());
+    }
+    received
+}
+
+#[test]
+fn test_streaming_complete_body() {
+    let (addr, _) = keep_alive_server(usize::MAX);
+    let (communicator, (receiver, sender)) = ServerCommunicator::new().unwrap();
+    let handle = communicator.start();
+
+    let (id, events) = sender.send_streaming(request(&addr)).unwrap();
+    let (response_id, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(response_id, id);
+    // the body is not copied into the response, but it is still counted
+    assert!(response.unwrap().body.is_empty());
+    assert_eq!(handle.metrics().bytes_received, 4);
+
+    let events = stream_events(&events);
+    assert!(matches!(&events[0], BodyEvent::Head(head) if head.result == 200));
+    assert!(matches!(&events[1], BodyEvent::Data { offset: 0, bytes } if bytes == b"data"));
+    assert!(matches!(events[2], BodyEvent::End { truncated: false }));
+}
+
+#[test]
+fn test_streaming_partial_body() {
+    // the server sends a half of the body and stops answering
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap_or(0) > 0 && line != "\r\n" {
+            line.clear();
+        }
+        stream
+            .write_all(b"HTTP/1.1 206 Partial Content\r\nContent-Length: 10\r\n\r\n12345")
+            .unwrap();
+        std::thread::sleep(Duration::from_secs(2));
+    });
+
+    let (_, (receiver, sender)) = start_communicator(CommunicatorConfig {
+        retry: RetryPolicy::none(),
+        timeouts: TimeoutConfig {
+            read: Some(Duration::from_millis(200)),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+
+    let (_, events) = sender.send_streaming(request(&addr)).unwrap();
+    let (_, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(matches!(
+        response,
+        Err(ServerCommunicatorError::TimeOutError(_))
+    ));
+
+    // the bytes which came through are not lost
+    let events = stream_events(&events);
+    assert!(matches!(&events[0], BodyEvent::Head(head) if head.result == 206));
+    assert!(matches!(&events[1], BodyEvent::Data { offset: 0, bytes } if bytes == b"12345"));
+    assert!(matches!(events[2], BodyEvent::End { truncated: true }));
+}
+
+#[test]
+fn test_cancel_queued_request() {
+    let (addr, accepted) = keep_alive_server(usize::MAX);
+    let (communicator, (receiver, sender)) = ServerCommunicator::new().unwrap();
+
+    let id = sender.send(request(&addr)).unwrap();
+    assert!(sender.cancel(id));
+    communicator.start();
+
+    let (response_id, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(response_id, id);
+    assert!(matches!(response, Err(ServerCommunicatorError::Cancelled)));
+    assert_eq!(accepted.load(Ordering::SeqCst), 0);
+
+    // the answered request can not be cancelled anymore
+    assert!(!sender.cancel(id));
+}
+
+#[test]
+fn test_cancel_frees_queue() {
+    let (addr, _) = keep_alive_server(usize::MAX);
+    let config = CommunicatorConfig {
+        queue_capacity: Some(1),
+        ..Default::default()
+    };
+    let (communicator, (receiver, sender)) = ServerCommunicator::with_config(config).unwrap();
+
+    let cancelled = sender.try_send(request(&addr)).unwrap();
+    assert!(matches!(
+        sender.try_send(request(&addr)),
+        Err(ServerCommunicatorError::QueueFull)
+    ));
+
+    // the place of the cancelled request is free before any worker takes it
+    assert!(sender.cancel(cancelled));
+    let sent = sender.try_send(request(&addr)).unwrap();
+    communicator.start();
+
+    let mut responses = (0..2)
+        .map(|_| receiver.recv_timeout(Duration::from_secs(5)).unwrap())
+        .collect::<std::collections::HashMap<_, _>>();
+    assert!(matches!(
+        responses.remove(&cancelled),
+        Some(Err(ServerCommunicatorError::Cancelled))
+    ));
+    assert_eq!(responses.remove(&sent).unwrap().unwrap().body, b"data");
+}
+
+#[test]
+fn test_cancel_request_in_progress() {
+    // the server reads the request and never answers
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap_or(0) > 0 {
+            line.clear();
+        }
+    });
+
+    let (_, (receiver, sender)) = start_communicator(CommunicatorConfig {
+        timeouts: TimeoutConfig {
+            read: Some(Duration::from_secs(10)),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+
+    let id = sender.send(request(&addr)).unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+    let cancelled = std::time::Instant::now();
+    assert!(sender.cancel(id));
+
+    // the connection is closed, the read timeout is not waited for and the request is not retried
+    let (_, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(matches!(response, Err(ServerCommunicatorError::Cancelled)));
+    assert!(cancelled.elapsed() < Duration::from_secs(1));
+}
+
+/// address on which nobody listens
+fn closed_address() -> std::net::SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap()
+}
+
+#[test]
+fn test_connect_fallback() {
+    let (addr, _) = keep_alive_server(usize::MAX);
+    let good = addr.parse().unwrap();
+
+    let transport = TcpTransport::new().with_addresses("multi.host", vec![closed_address(), good]);
+    let config = CommunicatorConfig {
+        retry: RetryPolicy::none(),
+        transport: Arc::new(transport.clone()),
+        ..Default::default()
+    };
+    let (_, (receiver, sender)) = start_communicator(config);
+
+    sender.send(request("multi.host")).unwrap();
+    let (_, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(response.unwrap().body, b"data");
+
+    // the next connection starts with the address which worked
+    assert_eq!(transport.last_good("multi.host"), Some(good));
+}
+
+#[test]
+fn test_staggered_connect() {
+    let slow = closed_address();
+    let fast = closed_address();
+
+    // the first address does not answer, the second one is tried without waiting for it
+    let started = std::time::Instant::now();
+    let attempt = move |addr| {
+        if addr == slow {
+            std::thread::sleep(Duration::from_secs(2));
+        }
+        Ok(addr)
+    };
+    let (addr, _) =
+        transport::staggered(vec![slow, fast], Duration::from_millis(100), attempt).unwrap();
+
+    assert_eq!(addr, fast);
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn test_connect_failures_reported() {
+    let addrs = vec![closed_address(), closed_address()];
+    let transport = TcpTransport::new().with_addresses("multi.host", addrs.clone());
+
+    let Err(err) = transport.connect("multi.host", Some(Duration::from_secs(1))) else {
+        panic!("nobody listens on the addresses");
+    };
+    assert_eq!(err.kind(), std::io::ErrorKind::ConnectionRefused);
+
+    let failure = err
+        .get_ref()
+        .unwrap()
+        .downcast_ref::<ConnectFailure>()
+        .unwrap();
+    assert_eq!(failure.host, "multi.host:80");
+    let attempted: HashSet<_> = failure.attempts.iter().map(|(addr, _)| *addr).collect();
+    assert_eq!(attempted, HashSet::from_iter(addrs));
+}
+
+#[test]
+fn test_global_throttle() {
+    let (addr, _) = keep_alive_server(usize::MAX);
+
+    let config = CommunicatorConfig {
+        throttle: Throttle::new(Bandwidth {
+            global: Some(400.0),
+            per_connection: None,
+        }),
+        ..Default::default()
+    };
+    // each response has 42 bytes, 420 bytes are read at 400 bytes per second
+    let elapsed = time_requests(&addr, config, 10);
+    assert!(elapsed >= Duration::from_millis(900), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(10), "{:?}", elapsed);
+}
+
+#[test]
+fn test_invalid_throttle_rates() {
+    let (addr, _) = keep_alive_server(usize::MAX);
+
+    // such rates would make the pacing panic, they are ignored
+    let throttle = Throttle::new(Bandwidth {
+        global: Some(0.0),
+        per_connection: Some(f64::NAN),
+    });
+    assert_eq!(throttle.limits(), Bandwidth::default());
+    throttle.set_global(Some(-1.0));
+    throttle.set_per_connection(Some(f64::INFINITY));
+    assert_eq!(throttle.limits(), Bandwidth::default());
+
+    let config = CommunicatorConfig {
+        throttle,
+        ..Default::default()
+    };
+    time_requests(&addr, config, 2);
+}
+
+#[test]
+fn test_throttle_slower_than_timeout() {
+    let (addr, _) = keep_alive_server(usize::MAX);
+
+    let mut config = CommunicatorConfig {
+        throttle: Throttle::new(Bandwidth {
+            global: None,
+            per_connection: Some(10.0),
+        }),
+        ..Default::default()
+    };
+    config.timeouts.overall = Some(Duration::from_millis(500));
+    config.retry.max_attempts = 1;
+    let (_, (receiver, sender)) = start_communicator(config);
+
+    // the response would take 4 seconds with the limit, the request fails at its deadline instead
+    sender.send(request(&addr)).unwrap();
+    let (_, response) = receiver.recv_timeout(Duration::from_secs(3)).unwrap();
+    assert!(
+        matches!(response, Err(ServerCommunicatorError::TimeOutError(_))),
+        "{:?}",
+        response
+    );
+}
+
+#[test]
+fn test_throttle_changed_at_runtime() {
+    let (addr, _) = keep_alive_server(usize::MAX);
+
+    let config = CommunicatorConfig {
+        throttle: Throttle::new(Bandwidth {
+            global: None,
+            per_connection: Some(2.0),
+        }),
+        ..Default::default()
+    };
+    let (handle, (receiver, sender)) = start_communicator(config);
+
+    // the response would take 20 seconds with the limit, it does not come before the limit is removed
+    sender.send(request(&addr)).unwrap();
+    assert!(receiver.recv_timeout(Duration::from_millis(300)).is_err());
+    handle.throttle().set_per_connection(None);
+
+    let (_, response) = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert_eq!(response.unwrap().body, b"data");
+}
+
+/// Reads as much as the throttle allows during the window, the connections take turns; returns the bytes read by each
+fn throttled_bytes(throttle: &Throttle, connections: usize, window: Duration) -> Vec<usize> {
+    let mut pacers: Vec<_> = (0..connections).map(|_| throttle.connection()).collect();
+    let mut read = vec![0; connections];
+    let started = std::time::Instant::now();
+    let mut now = started;
+    while now < started + window {
+        let mut delay = Duration::MAX;
+        for (pacer, read) in pacers.iter_mut().zip(&mut read) {
+            match throttle.allowance(pacer, now) {
+                Ok(bytes) => {
+                    throttle.received_at(pacer, bytes, now);
+                    *read += bytes;
+                    delay = Duration::ZERO;
+                }
+                Err(wait) => delay = delay.min(wait),
+            }
+        }
+        now += delay;
+    }
+    read
+}
+
+#[test]
+fn test_per_connection_throttle_window() {
+    let throttle = Throttle::new(Bandwidth {
+        global: None,
+        per_connection: Some(1000.0),
+    });
+
+    // each connection reads 10 bytes every 10 milliseconds
+    let read = throttled_bytes(&throttle, 2, Duration::from_secs(1));
+    for bytes in read {
+        assert!((1000..=1010).contains(&bytes), "{}", bytes);
+    }
+}
+
+#[test]
+fn test_global_throttle_window() {
+    let throttle = Throttle::new(Bandwidth {
+        global: Some(1000.0),
+        per_connection: Some(2000.0),
+    });
+
+    // the connections share the global limit, the lower rate sets the amount read at once
+    let read = throttled_bytes(&throttle, 2, Duration::from_secs(1));
+    let total: usize = read.iter().sum();
+    assert!((1000..=1010).contains(&total), "{:?}", read);
+}
+
+#[test]
+fn test_throttle_change_applies_to_connection() {
+    let throttle = Throttle::new(Bandwidth {
+        global: None,
+        per_connection: Some(10.0),
+    });
+    let mut connection = throttle.connection();
+    let now = std::time::Instant::now();
+
+    // at least one byte is read at once, the next one is allowed 100 milliseconds later
+    assert_eq!(throttle.allowance(&mut connection, now), Ok(1));
+    throttle.received_at(&mut connection, 1, now);
+    assert_eq!(
+        throttle.allowance(&mut connection, now),
+        Err(Duration::from_millis(100))
+    );
+    assert_eq!(
+        throttle.allowance(&mut connection, now + Duration::from_millis(100)),
+        Ok(1)
+    );
+
+    // the waiting connection can read at once without the limit
+    throttle.set_per_connection(None);
+    assert_eq!(throttle.allowance(&mut connection, now), Ok(usize::MAX));
+
+    // the pacing starts again with the new limit
+    throttle.set_per_connection(Some(100.0));
+    assert_eq!(throttle.allowance(&mut connection, now), Ok(1));
+    throttle.received_at(&mut connection, 1, now);
+    assert_eq!(
+        throttle.allowance(&mut connection, now),
+        Err(Duration::from_millis(10))
+    );
+}
+
+#[test]
+fn test_mock_communicator() {
+    let (mock, (receiver, sender)) = MockCommunicator::new()
+        .expect(
+            Expectation::new()
+                .with_method(HttpRequestMethod::GET)
+                .with_range(0, 4)
+                .with_response(206, &[("Content-Length", "4")], b"data"),
+        )
+        .expect(Expectation::new().with_error(ServerCommunicatorError::QueueFull))
+        .start();
+
+    // the expectations are matched regardless of the order of the requests
+    let mut ranged = request("mock.host");
+    ranged.add_header("range", "bytes=0-4");
+    let other = sender.send(request("mock.host")).unwrap();
+    let ranged = sender.send(ranged).unwrap();
+
+    let mut responses = std::collections::HashMap::new();
+    for _ in 0..2 {
+        let (id, response) = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+        responses.insert(id, response);
+    }
+    assert!(matches!(
+        responses.remove(&other),
+        Some(Err(ServerCommunicatorError::QueueFull))
+    ));
+    let response = responses.remove(&ranged).unwrap().unwrap();
+    assert_eq!((response.result, response.body), (206, b"data".to_vec()));
+    // the answered requests are not queued anymore
+    assert!(!sender.set_priority(ranged, Priority::High));
+
+    mock.assert_done();
+}
+
+#[test]
+#[should_panic(expected = "unused expectations")]
+fn test_mock_unused_expectation() {
+    let (mock, (receiver, sender)) = MockCommunicator::new()
+        .expect(Expectation::new().with_range(0, 4))
+        .start();
+
+    sender.send(request("mock.host")).unwrap();
+    let (_, response) = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert!(matches!(
+        response,
+        Err(ServerCommunicatorError::InvalidResponse(_))
+    ));
+
+    mock.assert_done();
+}
+
+/// ids of the responses in the order they come
+fn response_order(receiver: &Receiver<IdentifiedResponse>, responses: usize) -> Vec<RequestId> {
+    (0..responses)
+        .map(|_| {
+            let (id, response) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert_eq!(response.unwrap().body, b"data");
+            id
+        })
+        .collect()
+}
+
+#[test]
+fn test_priority_order() {
+    let (addr, _) = keep_alive_server(usize::MAX);
+    let (communicator, (receiver, sender)) = ServerCommunicator::new().unwrap();
+
+    let low = sender.with_priority(Priority::Low);
+    let bulk = (0..3)
+        .map(|_| low.send(request(&addr)).unwrap())
+        .collect::<Vec<_>>();
+    let normal = sender.send(request(&addr)).unwrap();
+    let critical = sender
+        .with_priority(Priority::Critical)
+        .send(request(&addr))
+        .unwrap();
+    communicator.start();
+
+    let order = response_order(&receiver, 5);
+    assert_eq!(order[..2], [critical, normal]);
+    assert_eq!(order[2..], bulk);
+}
+
+#[test]
+fn test_change_priority() {
+    let (addr, _) = keep_alive_server(usize::MAX);
+    let (communicator, (receiver, sender)) = ServerCommunicator::new().unwrap();
+
+    let first = sender.send(request(&addr)).unwrap();
+    let second = sender.send(request(&addr)).unwrap();
+    assert!(sender.set_priority(second, Priority::High));
+    communicator.start();
+
+    assert_eq!(response_order(&receiver, 2), [second, first]);
+    // the request is not queued anymore
+    assert!(!sender.set_priority(first, Priority::High));
+}
+
+#[test]
+fn test_priority_aging() {
+    use crate::priority::Scheduler;
+
+    let (sender, requests) = channel::request_channel();
+    let low = sender
+        .with_priority(Priority::Low)
+        .send(request("localhost"))
+        .unwrap();
+    let critical = sender
+        .with_priority(Priority::Critical)
+        .send(request("localhost"))
+        .unwrap();
+
+    // the low request waited long enough to overtake the later critical one
+    let now = std::time::Instant::now();
+    let priorities = sender.priorities();
+    priorities.queued_at(low, Priority::Low, now - Duration::from_millis(300));
+    priorities.queued_at(critical, Priority::Critical, now);
+
+    let mut scheduler = Scheduler::new(
+        requests,
+        priorities.clone(),
+        Some(Duration::from_millis(50)),
+    );
+    assert_eq!(scheduler.pop().unwrap().0, low);
+    assert_eq!(scheduler.pop().unwrap().0, critical);
+
+    // without aging the priority decides
+    let (sender, requests) = channel::request_channel();
+    let low = sender
+        .with_priority(Priority::Low)
+        .send(request("localhost"))
+        .unwrap();
+    let critical = sender
+        .with_priority(Priority::Critical)
+        .send(request("localhost"))
+        .unwrap();
+    sender
+        .priorities()
+        .queued_at(low, Priority::Low, now - Duration::from_millis(300));
+
+    let mut scheduler = Scheduler::new(requests, sender.priorities(), None);
+    assert_eq!(scheduler.pop().unwrap().0, critical);
+    assert_eq!(scheduler.pop().unwrap().0, low);
+}
@@ -0,0 +1,293 @@
+use std::{
+    io::{Read, Write},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::transport::{Aborter, Stream, Transport};
+
+/// amount of bytes, which is read at once, is the amount which can be received in this time
+const PACING_INTERVAL: Duration = Duration::from_millis(10);
+
+/// a waiting read checks so often, whether the limits were changed
+const MAX_SLEEP: Duration = Duration::from_millis(50);
+
+/// Limits of the received bytes per second, `None` means no limit
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Bandwidth {
+    /// all connections together
+    pub global: Option<f64>,
+    /// each connection separately
+    pub per_connection: Option<f64>,
+}
+
+impl Bandwidth {
+    /// a rate is valid, if it is finite and positive
+    pub fn is_valid_rate(rate: f64) -> bool {
+        rate.is_finite() && rate > 0.0
+    }
+
+    /// invalid rates are not limits, like in the rate limit
+    fn valid(self) -> Self {
+        let valid = |rate: Option<f64>| rate.filter(|rate| Self::is_valid_rate(*rate));
+        Self {
+            global: valid(self.global),
+            per_connection: valid(self.per_connection),
+        }
+    }
+}
+
+/// The moment when the next byte can be read
+#[derive(Debug)]
+pub(crate) struct Pacer {
+    next: Instant,
+    /// the limits, for which the moment was computed
+    version: u64,
+}
+
+impl Pacer {
+    fn new(version: u64) -> Self {
+        Self {
+            next: Instant::now(),
+            version,
+        }
+    }
+
+    /// the moment is computed for the old limits, so the pacing starts again
+    fn sync(&mut self, version: u64, now: Instant) {
+        if self.version != version {
+            self.next = now;
+            self.version = version;
+        }
+    }
+
+    fn delay(&self, rate: Option<f64>, now: Instant) -> Duration {
+        match rate {
+            Some(_) => self.next.saturating_duration_since(now),
+            None => Duration::ZERO,
+        }
+    }
+
+    fn advance(&mut self, rate: Option<f64>, bytes: usize, now: Instant) {
+        if let Some(rate) = rate {
+            self.next = self.next.max(now) + Duration::from_secs_f64(bytes as f64 / rate);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ThrottleState {
+    limits: Bandwidth,
+    global: Pacer,
+    version: u64,
+}
+
+/// Read-side bandwidth throttle. It is shared by the communicator and its clones, so the limits can be changed at runtime
+#[derive(Debug, Clone)]
+pub struct Throttle(Arc<Mutex<ThrottleState>>);
+
+impl Default for Throttle {
+    fn default() -> Self {
+        Self::new(Bandwidth::default())
+    }
+}
+
+impl Throttle {
+    /// rates, which are not finite and positive, are ignored
+    pub fn new(limits: Bandwidth) -> Self {
+        Self(Arc::new(Mutex::new(ThrottleState {
+            limits: limits.valid(),
+            global: Pacer::new(0),
+            version: 0,
+        })))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ThrottleState> {
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub fn limits(&self) -> Bandwidth {
+        self.lock().limits
+    }
+
+    /// the new limits apply to the reads in progress too, invalid rates are ignored
+    pub fn set_limits(&self, limits: Bandwidth) {
+        let mut state = self.lock();
+        state.limits = limits.valid();
+        state.version += 1;
+        state.global = Pacer::new(state.version);
+    }
+
+    pub fn set_global(&self, bytes_per_sec: Option<f64>) {
+        let limits = self.limits();
+        self.set_limits(Bandwidth {
+            global: bytes_per_sec,
+            ..limits
+        });
+    }
+
+    pub fn set_per_connection(&self, bytes_per_sec: Option<f64>) {
+        let limits = self.limits();
+        self.set_limits(Bandwidth {
+            per_connection: bytes_per_sec,
+            ..limits
+        });
+    }
+
+    pub(crate) fn connection(&self) -> Pacer {
+        Pacer::new(self.lock().version)
+    }
+
+    /// The amount of bytes, which the connection can read at `now`, or how long it has to wait for it
+    pub(crate) fn allowance(
+        &self,
+        connection: &mut Pacer,
+        now: Instant,
+    ) -> Result<usize, Duration> {
+        let mut state = self.lock();
+        let version = state.version;
+        state.global.sync(version, now);
+        connection.sync(version, now);
+
+        let Bandwidth {
+            global,
+            per_connection,
+        } = state.limits;
+        let delay = state
+            .global
+            .delay(global, now)
+            .max(connection.delay(per_connection, now));
+        if !delay.is_zero() {
+            return Err(delay);
+        }
+        Ok(
+            match global.into_iter().chain(per_connection).reduce(f64::min) {
+                Some(rate) => (rate * PACING_INTERVAL.as_secs_f64()).max(1.0) as usize,
+                None => usize::MAX,
+            },
+        )
+    }
+
+    /// the connection read the bytes at `now`
+    pub(crate) fn received_at(&self, connection: &mut Pacer, bytes: usize, now: Instant) {
+        let mut state = self.lock();
+        let version = state.version;
+        state.global.sync(version, now);
+        connection.sync(version, now);
+
+        let limits = state.limits;
+        state.global.advance(limits.global, bytes, now);
+        connection.advance(limits.per_connection, bytes, now);
+    }
+
+    /// Waits until the connection can read and returns the amount of bytes, which can be read at once.
+    ///
+    /// The waiting is a part of the read, so it fails with `TimedOut` after the read timeout of the connection
+    fn wait(&self, connection: &mut Pacer, timeout: Option<Duration>) -> std::io::Result<usize> {
+        let started = Instant::now();
+        loop {
+            let delay = match self.allowance(connection, Instant::now()) {
+                Ok(bytes) => return Ok(bytes),
+                Err(delay) => delay,
+            };
+
+            let left = timeout.map(|timeout| timeout.saturating_sub(started.elapsed()));
+            if left.is_some_and(|left| left.is_zero()) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "the throttled read took longer than the read timeout",
+                ));
+            }
+
+            let sleep = delay.min(MAX_SLEEP);
+            std::thread::sleep(left.map_or(sleep, |left| sleep.min(left)));
+        }
+    }
+
+    fn received(&self, connection: &mut Pacer, bytes: usize) {
+        self.received_at(connection, bytes, Instant::now());
+    }
+}
+
+/// Paces the reads of the connections of the inner transport according to the throttle
+#[derive(Debug)]
+pub struct ThrottledTransport {
+    inner: Arc<dyn Transport>,
+    throttle: Throttle,
+}
+
+impl ThrottledTransport {
+    pub fn new(inner: Arc<dyn Transport>, throttle: Throttle) -> Self {
+        Self { inner, throttle }
+    }
+}
+
+impl Transport for ThrottledTransport {
+    fn connect(&self, host: &str, timeout: Option<Duration>) -> std::io::Result<Box<dyn Stream>> {
+        Ok(Box::new(ThrottledStream {
+            inner: self.inner.connect(host, timeout)?,
+            pacer: self.throttle.connection(),
+            throttle: self.throttle.clone(),
+            read_timeout: Mutex::new(None),
+        }))
+    }
+}
+
+struct ThrottledStream {
+    inner: Box<dyn Stream>,
+    throttle: Throttle,
+    /// pacing of this connection
+    pacer: Pacer,
+    /// the waiting for the throttle can not take longer than the read itself
+    read_timeout: Mutex<Option<Duration>>,
+}
+
+impl Read for ThrottledStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let timeout = *self
+            .read_timeout
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        let len = self.throttle.wait(&mut self.pacer, timeout)?.min(buf.len());
+        let len = self.inner.read(&mut buf[..len])?;
+        self.throttle.received(&mut self.pacer, len);
+        Ok(len)
+    }
+}
+
+impl Write for ThrottledStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Stream for ThrottledStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.inner.set_read_timeout(timeout)?;
+        *self
+            .read_timeout
+            .lock()
+            .unwrap_or_else(|err| err.into_inner()) = timeout;
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.inner.set_write_timeout(timeout)
+    }
+
+    fn is_closed(&self) -> bool {
+        self.inner.is_closed()
+    }
+
+    fn aborter(&self) -> Option<Aborter> {
+        self.inner.aborter()
+    }
+}
@@ -0,0 +1,549 @@
+use std::{
+    borrow::Cow,
+    cell::Cell,
+    collections::{HashMap, VecDeque},
+    io::{ErrorKind, Read, Write},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    sync::{
+        Arc, Condvar, Mutex, MutexGuard,
+        mpsc::{Receiver, Sender, channel},
+    },
+    time::{Duration, Instant},
+};
+
+use http_message::uri::authority::Authority;
+
+/// Closes a stream from another thread, so its blocked reads and writes return
+pub type Aborter = Box<dyn Fn() + Send + Sync>;
+
+/// Bidirectional byte stream to a server
+pub trait Stream: Read + Write + Send {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()>;
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()>;
+    /// the server closed the connection (or sent data without a request), checked without blocking
+    fn is_closed(&self) -> bool;
+    /// None if the stream can not be closed from another thread
+    fn aborter(&self) -> Option<Aborter> {
+        None
+    }
+}
+
+/// The way connections to hosts are established
+pub trait Transport: std::fmt::Debug + Send + Sync {
+    /// `host` is the value of the Host header
+    fn connect(&self, host: &str, timeout: Option<Duration>) -> std::io::Result<Box<dyn Stream>>;
+}
+
+impl Stream for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+
+    fn is_closed(&self) -> bool {
+        if self.set_nonblocking(true).is_err() {
+            return true;
+        }
+
+        let mut byte = [0_u8];
+        let closed =
+            !matches!(self.peek(&mut byte), Err(err) if err.kind() == ErrorKind::WouldBlock);
+
+        closed || self.set_nonblocking(false).is_err()
+    }
+
+    fn aborter(&self) -> Option<Aborter> {
+        let stream = self.try_clone().ok()?;
+        Some(Box::new(move || {
+            let _ = stream.shutdown(std::net::Shutdown::Both);
+        }))
+    }
+}
+
+/// the Host header can omit the default port of http
+pub(crate) fn with_default_port(host: &str) -> Cow<'_, str> {
+    match Authority::parse(host) {
+        Ok(Authority { port: None, .. }) => Cow::Owned(format!("{}:80", host)),
+        _ => Cow::Borrowed(host),
+    }
+}
+
+/// Order in which the resolved addresses of a host are tried
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IpPreference {
+    /// IPv6 and IPv4 addresses alternately, starting with IPv6
+    #[default]
+    Ipv6First,
+    /// IPv4 and IPv6 addresses alternately, starting with IPv4
+    Ipv4First,
+    Ipv4Only,
+    Ipv6Only,
+}
+
+impl std::str::FromStr for IpPreference {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ipv6-first" => Ok(Self::Ipv6First),
+            "ipv4-first" => Ok(Self::Ipv4First),
+            "ipv4" => Ok(Self::Ipv4Only),
+            "ipv6" => Ok(Self::Ipv6Only),
+            _ => Err(format!(
+                "no such ip preference {}, use ipv6-first, ipv4-first, ipv4 or ipv6",
+                s
+            )),
+        }
+    }
+}
+
+impl IpPreference {
+    /// the addresses in the order of the attempts, the families are interleaved
+    fn order(self, addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+        let (v6, v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(SocketAddr::is_ipv6);
+        let (first, second) = match self {
+            Self::Ipv6First => (v6, v4),
+            Self::Ipv4First => (v4, v6),
+            Self::Ipv4Only => (v4, vec![]),
+            Self::Ipv6Only => (v6, vec![]),
+        };
+
+        let mut ordered = Vec::with_capacity(first.len() + second.len());
+        let (mut first, mut second) = (first.into_iter(), second.into_iter());
+        loop {
+            match (first.next(), second.next()) {
+                (None, None) => return ordered,
+                (first, second) => ordered.extend(first.into_iter().chain(second)),
+            }
+        }
+    }
+}
+
+/// All attempts to connect to the host failed, carried through the io error of the transport
+#[derive(Debug)]
+pub struct ConnectFailure {
+    pub host: String,
+    /// the failure of each address in the order of the attempts
+    pub attempts: Vec<(SocketAddr, std::io::Error)>,
+}
+
+impl std::fmt::Display for ConnectFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "connecting to {} failed", self.host)?;
+        for (addr, err) in &self.attempts {
+            write!(f, "; {}: {}", addr, err)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConnectFailure {}
+
+/// Connections over TCP. All addresses of the host are tried with staggered attempts:
+/// when an attempt is not finished after `attempt_delay`, the next address is tried in parallel, the first connection wins
+#[derive(Debug, Clone)]
+pub struct TcpTransport {
+    pub preference: IpPreference,
+    /// delay before the attempt to connect to the next address
+    pub attempt_delay: Duration,
+    /// addresses used instead of resolving the host (by its name in the Host header, with the port)
+    pub addresses: HashMap<String, Vec<SocketAddr>>,
+    /// the address of each host, which was connected the last time, it is tried first
+    last_good: Arc<Mutex<HashMap<String, SocketAddr>>>,
+}
+
+impl Default for TcpTransport {
+    fn default() -> Self {
+        Self {
+            preference: IpPreference::default(),
+            attempt_delay: Duration::from_millis(250),
+            addresses: HashMap::new(),
+            last_good: Default::default(),
+        }
+    }
+}
+
+impl TcpTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_preference(mut self, preference: IpPreference) -> Self {
+        self.preference = preference;
+        self
+    }
+
+    pub fn with_attempt_delay(mut self, attempt_delay: Duration) -> Self {
+        self.attempt_delay = attempt_delay;
+        self
+    }
+
+    /// `host` is resolved to `addrs`, like with an entry in /etc/hosts
+    pub fn with_addresses(mut self, host: &str, addrs: Vec<SocketAddr>) -> Self {
+        self.addresses
+            .insert(with_default_port(host).into_owned(), addrs);
+        self
+    }
+
+    /// the address which was connected the last time
+    pub fn last_good(&self, host: &str) -> Option<SocketAddr> {
+        let last_good = self.last_good.lock().unwrap_or_else(|err| err.into_inner());
+        last_good.get(with_default_port(host).as_ref()).copied()
+    }
+
+    /// addresses of the host in the order of the attempts
+    fn resolve(&self, host: &str) -> std::io::Result<Vec<SocketAddr>> {
+        let addrs = match self.addresses.get(host) {
+            Some(addrs) => addrs.clone(),
+            None => host.to_socket_addrs()?.collect(),
+        };
+        let mut addrs = self.preference.order(addrs);
+
+        if let Some(last_good) = self.last_good(host)
+            && let Some(position) = addrs.iter().position(|addr| *addr == last_good)
+        {
+            addrs[..=position].rotate_right(1);
+        }
+
+        Ok(addrs)
+    }
+
+    /// `timeout` is applied to each address separately
+    fn connect_staggered(
+        &self,
+        host: &str,
+        addrs: Vec<SocketAddr>,
+        timeout: Option<Duration>,
+    ) -> std::io::Result<TcpStream> {
+        let connect = move |addr| match timeout {
+            Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+            None => TcpStream::connect(addr),
+        };
+
+        match staggered(addrs, self.attempt_delay, connect) {
+            Ok((addr, stream)) => {
+                self.last_good
+                    .lock()
+                    .unwrap_or_else(|err| err.into_inner())
+                    .insert(host.to_string(), addr);
+                Ok(stream)
+            }
+            Err(failures) => {
+                let Some((_, last)) = failures.last() else {
+                    return Err(std::io::Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("{} was not resolved to any address", host),
+                    ));
+                };
+                Err(std::io::Error::new(
+                    last.kind(),
+                    ConnectFailure {
+                        host: host.to_string(),
+                        attempts: failures,
+                    },
+                ))
+            }
+        }
+    }
+}
+
+/// Attempts for the addresses in their order. When an attempt is not finished after `delay`, the next one starts in parallel,
+/// a failed attempt starts the next one immediately. Returns the first success or the failures of all attempts
+pub(crate) fn staggered<T: Send + 'static>(
+    addrs: Vec<SocketAddr>,
+    delay: Duration,
+    attempt: impl Fn(SocketAddr) -> std::io::Result<T> + Clone + Send + 'static,
+) -> Result<(SocketAddr, T), Vec<(SocketAddr, std::io::Error)>> {
+    let (sender, receiver) = channel();
+    let mut addrs = addrs.into_iter().peekable();
+    let mut running = 0;
+    let mut failures = vec![];
+
+    loop {
+        if let Some(addr) = addrs.next() {
+            let sender = sender.clone();
+            let attempt = attempt.clone();
+            // the results which come after the winner are dropped with the failed send
+            std::thread::spawn(move || {
+                let _ = sender.send((addr, attempt(addr)));
+            });
+            running += 1;
+        }
+        if running == 0 {
+            return Err(failures);
+        }
+
+        let finished = match addrs.peek() {
+            Some(_) => receiver.recv_timeout(delay).ok(),
+            None => receiver.recv().ok(),
+        };
+        match finished {
+            Some((addr, Ok(value))) => return Ok((addr, value)),
+            Some((addr, Err(err))) => {
+                #[cfg(debug_assertions)]
+                println!("Connecting to {} failed: {}", addr, err);
+                running -= 1;
+                failures.push((addr, err));
+            }
+            // the next address is tried in parallel
+            None => {}
+        }
+    }
+}
+
+impl Transport for TcpTransport {
+    fn connect(&self, host: &str, timeout: Option<Duration>) -> std::io::Result<Box<dyn Stream>> {
+        let host = with_default_port(host);
+        let addrs = self.resolve(&host)?;
+        let stream = self.connect_staggered(&host, addrs, timeout)?;
+
+        Ok(Box::new(stream))
+    }
+}
+
+#[cfg(unix)]
+pub use unix::UnixTransport;
+
+#[cfg(unix)]
+mod unix {
+    use std::{os::unix::net::UnixStream, path::PathBuf};
+
+    use super::*;
+
+    impl Stream for UnixStream {
+        fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+            UnixStream::set_read_timeout(self, timeout)
+        }
+
+        fn set_write_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+            UnixStream::set_write_timeout(self, timeout)
+        }
+
+        fn is_closed(&self) -> bool {
+            // peek is not stable for unix sockets, closed connections are detected by the failed request
+            false
+        }
+
+        fn aborter(&self) -> Option<Aborter> {
+            let stream = self.try_clone().ok()?;
+            Some(Box::new(move || {
+                let _ = stream.shutdown(std::net::Shutdown::Both);
+            }))
+        }
+    }
+
+    /// Connections to a Unix domain socket, all hosts are served by the same socket
+    #[derive(Debug, Clone)]
+    pub struct UnixTransport {
+        path: PathBuf,
+    }
+
+    impl UnixTransport {
+        pub fn new(path: impl Into<PathBuf>) -> Self {
+            Self { path: path.into() }
+        }
+    }
+
+    impl Transport for UnixTransport {
+        /// local sockets are connected immediately, so the timeout is not used
+        fn connect(
+            &self,
+            _host: &str,
+            _timeout: Option<Duration>,
+        ) -> std::io::Result<Box<dyn Stream>> {
+            Ok(Box::new(UnixStream::connect(&self.path)?))
+        }
+    }
+}
+
+#[derive(Default)]
+struct PipeState {
+    data: VecDeque<u8>,
+    /// one of the sides was dropped
+    closed: bool,
+}
+
+/// One direction of the in-memory connection
+#[derive(Default)]
+struct Pipe {
+    state: Mutex<PipeState>,
+    changed: Condvar,
+}
+
+impl Pipe {
+    fn lock(&self) -> MutexGuard<'_, PipeState> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn close(&self) {
+        self.lock().closed = true;
+        self.changed.notify_all();
+    }
+}
+
+/// One side of the in-memory connection, the data written into it is read from the other side
+pub struct LoopbackStream {
+    incoming: Arc<Pipe>,
+    outgoing: Arc<Pipe>,
+    read_timeout: Cell<Option<Duration>>,
+}
+
+impl LoopbackStream {
+    /// two connected sides
+    pub fn pair() -> (Self, Self) {
+        let (first, second) = (Arc::new(Pipe::default()), Arc::new(Pipe::default()));
+        let side = |incoming: &Arc<Pipe>, outgoing: &Arc<Pipe>| Self {
+            incoming: incoming.clone(),
+            outgoing: outgoing.clone(),
+            read_timeout: Cell::new(None),
+        };
+
+        (side(&first, &second), side(&second, &first))
+    }
+}
+
+impl Read for LoopbackStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let deadline = self
+            .read_timeout
+            .get()
+            .map(|timeout| Instant::now() + timeout);
+        let mut state = self.incoming.lock();
+
+        loop {
+            if !state.data.is_empty() || buf.is_empty() {
+                let len = buf.len().min(state.data.len());
+                state
+                    .data
+                    .drain(..len)
+                    .zip(buf.iter_mut())
+                    .for_each(|(byte, dest)| *dest = byte);
+                return Ok(len);
+            }
+
+            if state.closed {
+                return Ok(0);
+            }
+
+            state = match deadline {
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    if timeout.is_zero() {
+                        return Err(ErrorKind::WouldBlock.into());
+                    }
+                    self.incoming
+                        .changed
+                        .wait_timeout(state, timeout)
+                        .unwrap_or_else(|err| err.into_inner())
+                        .0
+                }
+                None => self
+                    .incoming
+                    .changed
+                    .wait(state)
+                    .unwrap_or_else(|err| err.into_inner()),
+            };
+        }
+    }
+}
+
+impl Write for LoopbackStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut state = self.outgoing.lock();
+        if state.closed {
+            return Err(ErrorKind::BrokenPipe.into());
+        }
+
+        state.data.extend(buf);
+        self.outgoing.changed.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Stream for LoopbackStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.read_timeout.set(timeout);
+        Ok(())
+    }
+
+    /// the written data is buffered without a limit, so writing never blocks
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn is_closed(&self) -> bool {
+        let state = self.incoming.lock();
+        state.closed || !state.data.is_empty()
+    }
+
+    fn aborter(&self) -> Option<Aborter> {
+        let (incoming, outgoing) = (self.incoming.clone(), self.outgoing.clone());
+        Some(Box::new(move || {
+            incoming.close();
+            outgoing.close();
+        }))
+    }
+}
+
+impl Drop for LoopbackStream {
+    fn drop(&mut self) {
+        self.incoming.close();
+        self.outgoing.close();
+    }
+}
+
+/// In-memory connections to a fake server in the same process, all hosts are served by the same listener
+#[derive(Debug, Clone)]
+pub struct LoopbackTransport {
+    connections: Sender<LoopbackStream>,
+}
+
+/// Server side of the [`LoopbackTransport`]
+pub struct LoopbackListener {
+    connections: Receiver<LoopbackStream>,
+}
+
+impl LoopbackTransport {
+    pub fn new() -> (Self, LoopbackListener) {
+        let (connections, receiver) = channel();
+
+        (
+            Self { connections },
+            LoopbackListener {
+                connections: receiver,
+            },
+        )
+    }
+}
+
+impl Transport for LoopbackTransport {
+    fn connect(&self, _host: &str, _timeout: Option<Duration>) -> std::io::Result<Box<dyn Stream>> {
+        let (client, server) = LoopbackStream::pair();
+
+        self.connections
+            .send(server)
+            .map_err(|_| std::io::Error::from(ErrorKind::ConnectionRefused))?;
+
+        Ok(Box::new(client))
+    }
+}
+
+impl LoopbackListener {
+    /// blocks until a new connection, fails when all transports are dropped
+    pub fn accept(&self) -> std::io::Result<LoopbackStream> {
+        self.connections
+            .recv()
+            .map_err(|_| std::io::Error::from(ErrorKind::NotConnected))
+    }
+
+    pub fn incoming(&self) -> impl Iterator<Item = LoopbackStream> + '_ {
+        self.connections.iter()
+    }
+}
@@ -0,0 +1,568 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    BodyFraming, CommunicatorConfig, HeaderName, HeaderValue, HttpRequest, HttpResponse,
+    IdentifiedRequest, IdentifiedResponse, RequestId, Sender, Serialize, ServerCommunicatorError,
+    cancel::Cancellation,
+    handle::{ShutdownPolicy, StatsCounters},
+    limit::Limiter,
+    metrics::{Metrics, MetricsRecorder},
+    pool::{ConnectionPool, PooledConnection},
+    priority::{Priorities, Scheduler},
+    proxy,
+    redirect::{is_redirect, redirected, visit_key},
+    stream::{BodyEvent, EventObserver, Streams},
+    transport::Stream,
+};
+use http_message::serialize::Deserialize;
+
+/// how often an idle worker checks, that the communicator was shut down
+const SHUTDOWN_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// State shared by all workers of the communicator
+pub(crate) struct Shared {
+    /// requests sent to the communicator, ordered by their priorities
+    pub requests: Mutex<Scheduler>,
+    pub config: CommunicatorConfig,
+    pub pool: ConnectionPool,
+    pub shutdown: AtomicBool,
+    pub stats: StatsCounters,
+    pub limiter: Limiter,
+    pub metrics: MetricsRecorder,
+    pub streams: Streams,
+    pub cancellation: Cancellation,
+    pub priorities: Priorities,
+}
+
+impl Shared {
+    pub fn metrics(&self) -> Metrics {
+        self.metrics
+            .snapshot(self.stats.retries.load(Ordering::SeqCst))
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+
+    /// requests in progress are not retried after the shutdown with cancel policy
+    pub fn is_cancelled(&self) -> bool {
+        self.is_shutdown() && self.config.shutdown_policy == ShutdownPolicy::Cancel
+    }
+}
+
+/// What the worker knows about the request besides its content
+pub(crate) struct RequestContext {
+    pub id: RequestId,
+    /// the parts of the responses are sent there, if the request is streamed
+    pub events: Option<Sender<BodyEvent>>,
+}
+
+pub(crate) struct Worker {
+    pub shared: Arc<Shared>,
+    pub respons: Sender<IdentifiedResponse>,
+}
+
+impl Worker {
+    pub fn new(shared: Arc<Shared>, respons: Sender<IdentifiedResponse>) -> Self {
+        Self { shared, respons }
+    }
+}
+
+pub(crate) fn has_connection_close(headers: &HashMap<HeaderName, HeaderValue>) -> bool {
+    headers.get(&"Connection".into()).is_some_and(|value| {
+        value
+            .value
+            .split(',')
+            .any(|token| token.trim().eq_ignore_ascii_case("close"))
+    })
+}
+
+/// The connection can be used for the next request only if the whole response was read and both sides agree to keep it
+pub(crate) fn is_reusable(request: &HttpRequest, response: &HttpResponse) -> bool {
+    response.protocol == "HTTP/1.1"
+        && !response.truncated
+        && !matches!(
+            response.body_framing(),
+            Ok(BodyFraming::UntilClose) | Err(_)
+        )
+        && !has_connection_close(&request.headers)
+        && !has_connection_close(&response.headers)
+}
+
+/// errors which happen, when the server has closed the connection before the request
+fn is_closed_connection_error(err: &std::io::Error) -> bool {
+    matches!(
+        err.kind(),
+        std::io::ErrorKind::BrokenPipe
+            | std::io::ErrorKind::ConnectionReset
+            | std::io::ErrorKind::ConnectionAborted
+            | std::io::ErrorKind::UnexpectedEof
+    )
+}
+
+/// read and write timeouts are reported with different kinds on different systems
+fn is_timeout_error(err: &std::io::Error) -> bool {
+    matches!(
+        err.kind(),
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+    )
+}
+
+pub(crate) fn map_timeout(err: ServerCommunicatorError) -> ServerCommunicatorError {
+    match err {
+        ServerCommunicatorError::TcpError(err) if is_timeout_error(&err) => {
+            ServerCommunicatorError::TimeOutError(format!("the exchange with the server: {}", err))
+        }
+        err => err,
+    }
+}
+
+pub(crate) fn host_of(request: &HttpRequest) -> Result<&str, ServerCommunicatorError> {
+    Ok(&request
+        .headers
+        .get(&"Host".into())
+        .ok_or(ServerCommunicatorError::NoHostNameinTheHeader)?
+        .value)
+}
+
+fn shortest(timeout: Option<Duration>, remaining: Option<Duration>) -> Option<Duration> {
+    match (timeout, remaining) {
+        (Some(timeout), Some(remaining)) => Some(timeout.min(remaining)),
+        (timeout, remaining) => timeout.or(remaining),
+    }
+}
+
+/// Reader of the response, each read from the connection waits at most until the deadline of the request.
+///
+/// The timeouts are set only once before the exchange, so otherwise a trickling body would never reach the deadline
+struct DeadlineReader<'a> {
+    inner: &'a mut BufReader<Box<dyn Stream>>,
+    read_timeout: Option<Duration>,
+    deadline: Option<Instant>,
+}
+
+impl DeadlineReader<'_> {
+    fn before_read(&self) -> std::io::Result<()> {
+        let Some(deadline) = self.deadline else {
+            return Ok(());
+        };
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "the deadline of the request was exceeded",
+            ));
+        }
+        self.inner
+            .get_ref()
+            .set_read_timeout(shortest(self.read_timeout, Some(remaining)))
+    }
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.inner.buffer().is_empty() {
+            self.before_read()?;
+        }
+        self.inner.read(buf)
+    }
+}
+
+impl BufRead for DeadlineReader<'_> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        if self.inner.buffer().is_empty() {
+            self.before_read()?;
+        }
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.inner.consume(amount)
+    }
+}
+
+impl Worker {
+    /// None if the communicator was shut down or all request senders were dropped
+    pub fn next_request(&mut self) -> Option<IdentifiedRequest> {
+        loop {
+            // read before the channel is checked, so a request arriving in between is not missed
+            let arrivals = self.shared.priorities.arrivals();
+            let mut requests = self
+                .shared
+                .requests
+                .lock()
+                .unwrap_or_else(|err| err.into_inner());
+
+            if self.shared.is_cancelled() {
+                self.cancel_queued(&mut requests);
+                return None;
+            }
+
+            if let Some(request) = requests.pop() {
+                return Some(request);
+            }
+            // after the shutdown only the requests which are already in the channel are handled
+            if self.shared.is_shutdown() {
+                return None;
+            }
+
+            if requests.is_finished() {
+                return None;
+            }
+
+            // the other workers can take requests meanwhile
+            drop(requests);
+            self.shared
+                .priorities
+                .wait_arrival(arrivals, SHUTDOWN_CHECK_INTERVAL);
+        }
+    }
+
+    fn cancel_queued(&self, requests: &mut Scheduler) {
+        for (id, _) in requests.drain() {
+            self.cancel(id);
+        }
+    }
+
+    fn cancel(&self, id: RequestId) {
+        self.shared.stats.cancelled.fetch_add(1, Ordering::SeqCst);
+
+        let response = Err(ServerCommunicatorError::Terminate);
+        self.shared.streams.finish(id, &response);
+        self.shared.cancellation.finish(id);
+        let _ = self.respons.send((id, response));
+    }
+
+    /// Sends the response to the caller, false if nobody waits for responses anymore.
+    ///
+    /// `started` is the moment, when the worker took the request
+    pub fn respond(
+        &self,
+        id: RequestId,
+        started: Instant,
+        response: Result<HttpResponse, ServerCommunicatorError>,
+    ) -> bool {
+        self.shared.metrics.total(started.elapsed());
+        self.shared.streams.finish(id, &response);
+        self.shared.cancellation.finish(id);
+        let counter = match response {
+            Ok(_) => &self.shared.stats.completed,
+            Err(ServerCommunicatorError::Cancelled) => &self.shared.stats.cancelled,
+            Err(_) => &self.shared.stats.failed,
+        };
+        counter.fetch_add(1, Ordering::SeqCst);
+
+        // errors are sent to the caller as well, so it can react to them immediately
+        let sent = self.respons.send((id, response)).is_ok();
+
+        #[cfg(debug_assertions)]
+        if sent {
+            println!("The value was send through the channel")
+        }
+
+        sent
+    }
+
+    pub fn context(&self, id: RequestId) -> RequestContext {
+        RequestContext {
+            id,
+            events: self.shared.streams.get(id),
+        }
+    }
+
+    /// deadline of a request, which starts now
+    pub fn deadline(&self) -> Option<Instant> {
+        self.shared
+            .config
+            .timeouts
+            .overall
+            .map(|timeout| Instant::now() + timeout)
+    }
+
+    /// Handles the request with retries and redirects according to the policies of the communicator.
+    ///
+    /// If the request is streamed, the parts of all responses are sent as its events
+    pub fn handle(
+        &self,
+        request: &HttpRequest,
+        context: &RequestContext,
+    ) -> Result<HttpResponse, ServerCommunicatorError> {
+        self.follow_redirects(request, self.attempts(request, None, context), context)
+    }
+
+    /// Handles the request like `handle`, but its first attempt was already made and got the response
+    pub fn handle_retryable(
+        &self,
+        request: &HttpRequest,
+        response: Result<HttpResponse, ServerCommunicatorError>,
+        context: &RequestContext,
+    ) -> Result<HttpResponse, ServerCommunicatorError> {
+        let response = self.attempts(request, Some(response), context);
+        self.follow_redirects(request, response, context)
+    }
+
+    /// Follows the redirects starting with the response to the request, each redirected request is retried on its own
+    pub fn follow_redirects(
+        &self,
+        request: &HttpRequest,
+        response: Result<HttpResponse, ServerCommunicatorError>,
+        context: &RequestContext,
+    ) -> Result<HttpResponse, ServerCommunicatorError> {
+        let max_hops = self.shared.config.redirect.max_hops;
+        let mut visited = HashSet::from([visit_key(request)]);
+        let mut request = Cow::Borrowed(request);
+        let mut response = response;
+
+        let mut hops = 0;
+        loop {
+            let redirect = match &response {
+                Ok(redirect) if max_hops > 0 && is_redirect(redirect) => redirect,
+                _ => return response,
+            };
+            if hops == max_hops {
+                return Err(ServerCommunicatorError::TooManyRedirects(max_hops));
+            }
+
+            let next = redirected(&request, redirect)?;
+            if !visited.insert(visit_key(&next)) {
+                return Err(ServerCommunicatorError::RedirectLoop(visit_key(&next)));
+            }
+
+            #[cfg(debug_assertions)]
+            println!("Redirected to {}", visit_key(&next));
+            response = self.attempts(&next, None, context);
+            request = Cow::Owned(next);
+            hops += 1;
+        }
+    }
+
+    /// Handles the request with retries according to the policy of the communicator.
+    ///
+    /// `first` is the response of the first attempt, if it was already made, it counts against the attempts of the policy
+    fn attempts(
+        &self,
+        request: &HttpRequest,
+        first: Option<Result<HttpResponse, ServerCommunicatorError>>,
+        context: &RequestContext,
+    ) -> Result<HttpResponse, ServerCommunicatorError> {
+        let policy = &self.shared.config.retry;
+        let deadline = self.deadline();
+
+        let mut attempt = 1;
+        let mut response = first.unwrap_or_else(|| self.workflow(request, deadline, context));
+        loop {
+            // the failure of the aborted connection is not a failure of the request
+            if self.shared.cancellation.is_cancelled(context.id) {
+                return Err(ServerCommunicatorError::Cancelled);
+            }
+            if let Err(err) = &response {
+                self.shared.metrics.error(err);
+            }
+
+            if attempt >= policy.max_attempts
+                || !policy.should_retry(request, &response)
+                || self.shared.is_cancelled()
+            {
+                return response;
+            }
+
+            let backoff = policy.backoff(attempt);
+            if deadline.is_some_and(|deadline| Instant::now() + backoff >= deadline) {
+                return response;
+            }
+
+            #[cfg(debug_assertions)]
+            println!("Attempt {} failed, retrying in {:?}", attempt, backoff);
+            std::thread::sleep(backoff);
+            attempt += 1;
+            self.shared.stats.retries.fetch_add(1, Ordering::SeqCst);
+            response = self.workflow(request, deadline, context);
+        }
+    }
+
+    fn workflow(
+        &self,
+        request: &HttpRequest,
+        deadline: Option<Instant>,
+        context: &RequestContext,
+    ) -> Result<HttpResponse, ServerCommunicatorError> {
+        let cancellation = &self.shared.cancellation;
+        if cancellation.is_cancelled(context.id) {
+            return Err(ServerCommunicatorError::Cancelled);
+        }
+
+        let mut connection = self.shared.pool.get(host_of(request)?)?;
+        self.connected(&mut connection);
+        self.shared.limiter.before_request();
+
+        let in_progress = cancellation
+            .start(context.id, connection.stream().aborter())
+            .ok_or(ServerCommunicatorError::Cancelled)?;
+
+        let response = match self.exchange(&mut connection, request, deadline, context) {
+            // the server could close the idle connection right before the request, so it is repeated on a new one
+            Err(ServerCommunicatorError::TcpError(err))
+                if connection.reused && is_closed_connection_error(&err) =>
+            {
+                connection.reconnect()?;
+                self.connected(&mut connection);
+                if !in_progress.restart(connection.stream().aborter()) {
+                    return Err(ServerCommunicatorError::Cancelled);
+                }
+                self.exchange(&mut connection, request, deadline, context)?
+            }
+            response => response?,
+        };
+        // the connection must not be aborted after it is returned to the pool
+        drop(in_progress);
+
+        // the connection of a request, which was cancelled just now, could be already aborted
+        if is_reusable(request, &response) && !cancellation.is_cancelled(context.id) {
+            connection.keep_alive();
+        }
+
+        Ok(response)
+    }
+
+    /// records the connect time, if the connection is new
+    pub fn connected(&self, connection: &mut PooledConnection) {
+        if let Some(time) = connection.connect_time.take() {
+            self.shared.metrics.connect(time);
+        }
+    }
+
+    /// `body_len` is the length of the received body, the body of a streamed response is not kept
+    fn received(&self, response: &HttpResponse, body_len: usize) {
+        self.shared.limiter.after_response(body_len);
+        self.shared.metrics.response(response, body_len);
+    }
+
+    fn exchange(
+        &self,
+        connection: &mut PooledConnection,
+        request: &HttpRequest,
+        deadline: Option<Instant>,
+        context: &RequestContext,
+    ) -> Result<HttpResponse, ServerCommunicatorError> {
+        self.prepare(connection, deadline)?;
+
+        let proxy = self.shared.config.proxy.as_ref();
+        let forwarded = proxy.is_some_and(|proxy| proxy.forwards(request));
+        let outgoing = proxy::outgoing(proxy, request)?;
+
+        let sent = Instant::now();
+        Self::write_request(connection, &outgoing).map_err(map_timeout)?;
+        let response = self
+            .read_response(connection, Some(sent), deadline, context.events.as_ref())
+            .map_err(map_timeout)?;
+        proxy::check_response(forwarded, response)
+    }
+
+    /// sets the timeouts of the connection, nothing can take longer than the rest of the request deadline
+    pub fn prepare(
+        &self,
+        connection: &mut PooledConnection,
+        deadline: Option<Instant>,
+    ) -> Result<(), ServerCommunicatorError> {
+        let timeouts = &self.shared.config.timeouts;
+        let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        if remaining == Some(Duration::ZERO) {
+            return Err(ServerCommunicatorError::TimeOutError(
+                "the request, the deadline was exceeded".to_string(),
+            ));
+        }
+
+        connection
+            .stream()
+            .set_write_timeout(shortest(timeouts.write, remaining))?;
+        connection
+            .stream()
+            .set_read_timeout(shortest(timeouts.read, remaining))?;
+
+        Ok(())
+    }
+
+    pub fn write_request(
+        connection: &mut PooledConnection,
+        request: &HttpRequest,
+    ) -> Result<(), ServerCommunicatorError> {
+        let mut writer = BufWriter::new(connection.stream_mut());
+        request.serialize_into(&mut writer)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Reads the next response from the connection before the deadline, `sent` is the moment when its request was sent.
+    ///
+    /// The time to the first byte is recorded only if `sent` is known
+    pub fn read_response(
+        &self,
+        connection: &mut PooledConnection,
+        sent: Option<Instant>,
+        deadline: Option<Instant>,
+        events: Option<&Sender<BodyEvent>>,
+    ) -> Result<HttpResponse, ServerCommunicatorError> {
+        let mut reader = DeadlineReader {
+            inner: &mut connection.reader,
+            read_timeout: self.shared.config.timeouts.read,
+            deadline,
+        };
+        if reader.fill_buf()?.is_empty() {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "The lenght of read data is 0",
+            ))?
+        }
+        if let Some(sent) = sent {
+            self.shared.metrics.first_byte(sent.elapsed());
+        }
+
+        let limits = &self.shared.config.limits;
+        let (response, body_len) = match events {
+            Some(events) => {
+                let mut observer = EventObserver::new(events);
+                let response =
+                    HttpResponse::deserialize_observed(&mut reader, limits, &mut observer)?;
+                (response, observer.received)
+            }
+            None => {
+                let response = HttpResponse::deserialize_with_limits(&mut reader, limits)?;
+                let body_len = response.body.len();
+                (response, body_len)
+            }
+        };
+
+        self.received(&response, body_len);
+        Ok(response)
+    }
+
+    pub fn run(mut self) {
+        while let Some(batch) = self.next_batch() {
+            let started = Instant::now();
+            let _in_flight = self.shared.limiter.start(batch.len());
+            let delivered = if batch.len() > 1 {
+                self.pipeline(batch, started)
+            } else {
+                batch.into_iter().all(|(id, request)| {
+                    self.respond(id, started, self.handle(&request, &self.context(id)))
+                })
+            };
+
+            if !delivered {
+                // nobody waits for responses anymore
+                break;
+            }
+        }
+    }
+}
@@ -1,73 +1,10 @@
 use crate::managers::{basic_manager::BasicManager, random_manager::RandomManager};
 use crate::real_manager_wrapper::test_with_server;
-
-mod arguments {
-    use super::ManagerType;
-
-    pub fn get_addr(args: &Vec<String>) -> Result<String, std::io::Error> {
-        let mut iterator = args.iter();
-
-        while let Some(val) = iterator.next() {
-            if val.as_str() == "--addr" {
-                break;
-            }
-        }
-
-        if let Some(addr) = iterator.next() {
-            Ok(addr.clone())
-        } else {
-            Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "The run script must include server address",
-            ))
-        }
-    }
-
-    pub fn get_hash(args: &Vec<String>) -> Result<String, std::io::Error> {
-        let mut iterator = args.iter();
-
-        while let Some(val) = iterator.next() {
-            if val.as_str() == "--hash" {
-                break;
-            }
-        }
-
-        if let Some(hash) = iterator.next() {
-            Ok(hash.clone())
-        } else {
-            Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "The run script must include hash of the data",
-            ))
-        }
-    }
-
-    pub fn get_manager(args: &Vec<String>) -> Result<ManagerType, std::io::Error> {
-        let mut iterator = args.iter();
-
-        while let Some(val) = iterator.next() {
-            if val.as_str() == "--manager" {
-                break;
-            }
-        }
-
-        if let Some(manager) = iterator.next() {
-            match manager.as_str() {
-                "basic_manager" => Ok(ManagerType::BasicManager),
-                "random_manager" => Ok(ManagerType::RandomManager),
-                _ => Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidInput,
-                    "no such manager",
-                )),
-            }
-        } else {
-            Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "The run script must include server address",
-            ))
-        }
-    }
-}
+use http_message::http_messages::path::Path;
+use server_communicator::{
+    Bandwidth, CommunicatorConfig, ProxyConfig, ShutdownPolicy, TcpTransport, Throttle,
+};
+use std::{path::PathBuf, str::FromStr, sync::Arc, time::Duration};
 
 #[derive(Debug)]
 pub enum ManagerType {
@@ -76,10 +13,10 @@ pub enum ManagerType {
 }
 
 impl ManagerType {
-    fn start(self, addr: &str) -> String {
+    fn start(self, addr: &str, path: Path, config: CommunicatorConfig) -> String {
         match self {
-            ManagerType::BasicManager => test_with_server::<BasicManager>(addr),
-            ManagerType::RandomManager => test_with_server::<RandomManager>(addr),
+            ManagerType::BasicManager => test_with_server::<BasicManager>(addr, path, config),
+            ManagerType::RandomManager => test_with_server::<RandomManager>(addr, path, config),
         }
     }
 }
@@ -87,19 +24,20 @@ impl ManagerType {
 #[derive(Debug)]
 pub struct Application {
     server_addr: String,
+    path: Path,
     manager_type: ManagerType,
     hash: String,
+    config: CommunicatorConfig,
 }
 
 impl Application {
-    fn try_get_arg(key: (&str, &str)) -> Result<String, std::io::Error> {
-        let try_arg: Vec<String> = std::env::args().collect();
-
-        let mut iter = try_arg.into_iter();
+    /// value of the script argument, environment variables are not read
+    fn try_get_script_arg(name: &str) -> Result<Option<String>, std::io::Error> {
+        let mut iter = std::env::args();
 
-        if let Some(_) = iter.find(|arg| arg == key.0) {
+        if iter.any(|arg| arg == name) {
             return match iter.next() {
-                Some(val) => Ok(val),
+                Some(val) => Ok(Some(val)),
                 None => Err(std::io::Error::new(
                     std::io::ErrorKind::InvalidData,
                     "incorrect argument",
@@ -107,6 +45,14 @@ impl Application {
             };
         }
 
+        Ok(None)
+    }
+
+    fn try_get_arg(key: (&str, &str)) -> Result<String, std::io::Error> {
+        if let Some(val) = Self::try_get_script_arg(key.0)? {
+            return Ok(val);
+        }
+
         std::env::var(key.1).map_err(|_| {
             std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
@@ -118,6 +64,112 @@ impl Application {
         })
     }
 
+    /// None if the argument was not specified
+    fn try_parse_arg<T: FromStr>(key: (&str, &str)) -> Result<Option<T>, std::io::Error>
+    where
+        T::Err: std::fmt::Display,
+    {
+        Self::try_get_arg(key)
+            .ok()
+            .map(|value| {
+                value.parse().map_err(|err| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("invalid value of {}: {}", key.0, err),
+                    )
+                })
+            })
+            .transpose()
+    }
+
+    /// bytes per second, the value must be finite and positive
+    fn try_get_bandwidth(key: (&str, &str)) -> Result<Option<f64>, std::io::Error> {
+        match Self::try_parse_arg::<f64>(key)? {
+            Some(rate) if !Bandwidth::is_valid_rate(rate) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "invalid value of {}: {} is not a positive number",
+                    key.0, rate
+                ),
+            )),
+            rate => Ok(rate),
+        }
+    }
+
+    /// timeout in milliseconds, 0 disables it
+    fn try_get_timeout(
+        key: (&str, &str),
+        timeout: &mut Option<Duration>,
+    ) -> Result<(), std::io::Error> {
+        if let Some(ms) = Self::try_parse_arg::<u64>(key)? {
+            *timeout = (ms > 0).then(|| Duration::from_millis(ms));
+        }
+        Ok(())
+    }
+
+    fn get_config() -> Result<CommunicatorConfig, std::io::Error> {
+        let mut config = CommunicatorConfig {
+            // requests which are still queued, when the download is finished, are not needed
+            shutdown_policy: ShutdownPolicy::Cancel,
+            ..Default::default()
+        };
+
+        let timeouts = &mut config.timeouts;
+        Self::try_get_timeout(
+            ("--connect-timeout", "CONNECT_TIMEOUT_MS"),
+            &mut timeouts.connect,
+        )?;
+        Self::try_get_timeout(("--read-timeout", "READ_TIMEOUT_MS"), &mut timeouts.read)?;
+        Self::try_get_timeout(("--write-timeout", "WRITE_TIMEOUT_MS"), &mut timeouts.write)?;
+        Self::try_get_timeout(("--timeout", "REQUEST_TIMEOUT_MS"), &mut timeouts.overall)?;
+
+        let retry = &mut config.retry;
+        if let Some(max_attempts) = Self::try_parse_arg(("--max-attempts", "MAX_ATTEMPTS"))? {
+            retry.max_attempts = max_attempts;
+        }
+        if let Some(ms) = Self::try_parse_arg(("--backoff", "BACKOFF_MS"))? {
+            retry.initial_backoff = Duration::from_millis(ms);
+        }
+        if let Some(ms) = Self::try_parse_arg(("--max-backoff", "MAX_BACKOFF_MS"))? {
+            retry.max_backoff = Duration::from_millis(ms);
+        }
+
+        if let Some(preference) = Self::try_parse_arg(("--ip-preference", "IP_PREFERENCE"))? {
+            config.transport = Arc::new(TcpTransport::new().with_preference(preference));
+        }
+
+        // background downloads should not saturate the link
+        config.throttle = Throttle::new(Bandwidth {
+            global: Self::try_get_bandwidth(("--bandwidth", "BANDWIDTH"))?,
+            per_connection: Self::try_get_bandwidth((
+                "--connection-bandwidth",
+                "CONNECTION_BANDWIDTH",
+            ))?,
+        });
+
+        // the whole traffic can be written into a file to replay the session later
+        config.record = Self::try_get_arg(("--record", "RECORD_FILE"))
+            .ok()
+            .map(PathBuf::from);
+
+        // HTTP_PROXY and NO_PROXY are used like by other http clients, the arguments override them
+        config.proxy = match Self::try_get_script_arg("--proxy")? {
+            Some(address) if !address.is_empty() => Some(ProxyConfig::new(&address)),
+            Some(_) => None,
+            None => ProxyConfig::from_env(),
+        };
+        if let Some(no_proxy) = Self::try_get_script_arg("--no-proxy")? {
+            config.proxy = config.proxy.map(|proxy| proxy.with_no_proxy(&no_proxy));
+        }
+        if let Some(mode) = Self::try_parse_arg(("--proxy-mode", "PROXY_MODE"))?
+            && let Some(proxy) = &mut config.proxy
+        {
+            proxy.mode = mode;
+        }
+
+        Ok(config)
+    }
+
     pub fn new() -> Result<Self, std::io::Error> {
         let addr = Self::try_get_arg(("--addr", "ADDR"));
         let manager = Self::try_get_arg(("--manager", "MANAGER"));
@@ -137,15 +189,30 @@ impl Application {
 
         let hash = Self::try_get_arg(("--hash", "HASH"));
 
+        // the path is optional, by default the root of the server is downloaded
+        let path = match Self::try_get_arg(("--path", "RESOURCE_PATH")) {
+            Ok(path) => Path::new(path).map_err(|err| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("invalid resource path: {}", err),
+                )
+            })?,
+            Err(_) => Path::default(),
+        };
+
         Ok(Self {
             server_addr: addr?,
+            path,
             manager_type: manager?,
             hash: hash?,
+            config: Self::get_config()?,
         })
     }
 
     pub fn start(self) -> Result<(), std::io::Error> {
-        let res = self.manager_type.start(&self.server_addr);
+        let res = self
+            .manager_type
+            .start(&self.server_addr, self.path, self.config);
 
         if res == self.hash {
             println!("Hashes are the same");
@@ -1,25 +1,82 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
 use data_manager::data_holder::DataHolder;
 use errors::ClientError;
 use http_message::http_messages::{path::Path, request::HttpRequestMethod};
 use server_communicator::*;
 
 pub struct Client {
-    sender: Sender<HttpRequest>,
-    receiver: Receiver<HttpResponse>,
-    // ussually http servers answer with content-range header, but as our server does not do it, I will need this field(
-    last_chunk_start_point: usize,
+    sender: RequestSender,
+    receiver: Receiver<IdentifiedResponse>,
+    // ussually http servers answer with content-range header, but as our server does not do it, the start of each requested range is kept until its response comes
+    outstanding: HashMap<RequestId, Outstanding>,
+    /// how long to wait for the next response, the communicator reports failed requests by itself, so it is just a safety net
+    response_timeout: Option<Duration>,
     data_len: usize,
     addr: String,
+    path: Path,
+    validators: Validators,
 }
 
-impl Client {
-    fn get_server_addr(&self) -> &String {
-        &self.addr
+/// Requested range, which was not answered yet
+struct Outstanding {
+    start: usize,
+    /// parts of the body as they come, so the data is not lost if the request fails
+    events: Receiver<BodyEvent>,
+}
+
+/// Validators of the resource version, taken from the first response
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl Validators {
+    fn from_response(response: &HttpResponse) -> Self {
+        let get = |name: &str| {
+            response
+                .headers
+                .get(&name.into())
+                .map(|value| value.value.clone())
+        };
+
+        Self {
+            etag: get("ETag"),
+            last_modified: get("Last-Modified"),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
     }
 
+    /// value of If-Range header, weak etags can not be used there (RFC 9110 13.1.5)
+    fn if_range(&self) -> Option<&str> {
+        match &self.etag {
+            Some(etag) if !etag.starts_with("W/") => Some(etag),
+            _ => self.last_modified.as_deref(),
+        }
+    }
+
+    /// validators which are present in both responses must be the same
+    fn matches(&self, other: &Self) -> bool {
+        let same = |a: &Option<String>, b: &Option<String>| match (a, b) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        };
+
+        same(&self.etag, &other.etag) && same(&self.last_modified, &other.last_modified)
+    }
+}
+
+impl Client {
     fn check_response(response: HttpResponse) -> Result<(Vec<u8>, usize), ServerCommunicatorError> {
         if response.result != 206 && response.result != 200 {
-            return Err(ServerCommunicatorError::SerializeError(format!(
+            return Err(ServerCommunicatorError::InvalidResponse(format!(
                 "the reponse code must be 206(or 200 FULL Content): {}-{}",
                 response.result_string, response.result
             )));
@@ -27,7 +84,7 @@ impl Client {
 
         let len = response.body.len();
         if len == 0 {
-            return Err(ServerCommunicatorError::SerializeError(
+            return Err(ServerCommunicatorError::InvalidResponse(
                 "The length of body is 0".to_string(),
             ));
         }
@@ -35,49 +92,230 @@ impl Client {
         Ok((response.body, len))
     }
 
-    pub fn new(
-        addr: &str,
-        sender: Sender<HttpRequest>,
-        receiver: Receiver<HttpResponse>,
-    ) -> Result<Self, ServerCommunicatorError> {
-        let mut request = HttpRequest::new(HttpRequestMethod::GET, Path::default(), "HTTP/1.1");
+    /// Check that the response belongs to the same version of the resource as the first one
+    fn check_version(&self, response: &HttpResponse) -> Result<(), ClientError> {
+        let validators = Validators::from_response(response);
 
-        request.add_header("Host", addr);
-        request.add_header("User-Agent", "Rust-Client/1.0");
-        request.add_header("Connection", "close");
+        if !self.validators.matches(&validators) {
+            return Err(ClientError::ResourceChanged);
+        }
+
+        if response.result == 200 {
+            // If-Range was not satisfied, so the server sent the whole new version
+            if self.validators.if_range().is_some() && validators.is_empty() {
+                return Err(ClientError::ResourceChanged);
+            }
+
+            if Self::get_content_length(response)? != self.data_len {
+                return Err(ClientError::ResourceChanged);
+            }
+        }
 
-        sender.send(request)?;
+        Ok(())
+    }
 
-        let response = receiver.recv_timeout(std::time::Duration::from_secs(10))?;
+    fn get_content_length(response: &HttpResponse) -> Result<usize, ServerCommunicatorError> {
         if let Some(length) = response.headers.get(&"Content-Length".into()) {
             #[cfg(debug_assertions)]
             println!("Parsing: {}", length.value);
-            let len = length
+            length
                 .value
                 .chars()
-                .filter(|char| char.is_digit(10))
+                .filter(|char| char.is_ascii_digit())
                 .collect::<String>()
                 .parse::<usize>()
                 .map_err(|err| {
-                    ServerCommunicatorError::SerializeError(format!(
+                    ServerCommunicatorError::InvalidResponse(format!(
                         "Error while trying to parse content of content-length header: {}",
                         err
                     ))
-                })?;
-
-            Ok(Self {
-                addr: addr.to_string(),
-                sender,
-                receiver,
-                last_chunk_start_point: 0,
-                data_len: len,
-            })
+                })
         } else {
-            Err(ServerCommunicatorError::SerializeError(
+            Err(ServerCommunicatorError::InvalidResponse(
                 "The server does not specify content-length".to_string(),
             ))
         }
     }
+
+    /// Body of the last response to the request, the body of a streamed response comes only with its events
+    fn streamed_body(outstanding: Outstanding) -> Vec<u8> {
+        let mut body = vec![];
+
+        // the events end before the response is sent, so all of them are already there
+        for event in outstanding.events.try_iter() {
+            match event {
+                // a retried or redirected attempt starts again
+                BodyEvent::Head(_) => body.clear(),
+                BodyEvent::Data { offset, bytes } if offset == body.len() => body.extend(bytes),
+                BodyEvent::Data { .. } => {}
+                BodyEvent::End { .. } => break,
+            }
+        }
+
+        body
+    }
+
+    /// The longest beginning of a body, which came through before the request failed, with its bounds.
+    ///
+    /// Only bodies of the same version of the resource are taken
+    fn salvage(&self, outstanding: Outstanding) -> Option<(Vec<u8>, (usize, usize))> {
+        // bodies of the attempts with an acceptable head, with their starts
+        let mut bodies: Vec<(Vec<u8>, usize)> = vec![];
+        let mut accepted = false;
+
+        // the events end before the response is sent, so all of them are already there
+        for event in outstanding.events.try_iter() {
+            match event {
+                BodyEvent::Head(head) => {
+                    accepted =
+                        matches!(head.result, 200 | 206) && self.check_version(&head).is_ok();
+                    if accepted {
+                        // full content always starts from the begining of the resource
+                        let start = if head.result == 200 {
+                            0
+                        } else {
+                            outstanding.start
+                        };
+                        bodies.push((vec![], start));
+                    }
+                }
+                BodyEvent::Data { offset, bytes } => {
+                    if accepted
+                        && let Some((body, _)) = bodies.last_mut()
+                        && body.len() == offset
+                    {
+                        body.extend(bytes);
+                    }
+                }
+                BodyEvent::End { .. } => break,
+            }
+        }
+
+        bodies
+            .into_iter()
+            .filter(|(body, _)| !body.is_empty())
+            .max_by_key(|(body, _)| body.len())
+            .map(|(body, start)| {
+                let end = start + body.len();
+                (body, (start, end))
+            })
+    }
+
+    /// Request the whole resource to find out its length and validators.
+    ///
+    /// Only the head is needed, so the request is cancelled when it comes and the body is not downloaded
+    fn probe(&mut self) -> Result<(), ServerCommunicatorError> {
+        let mut request = HttpRequest::new(HttpRequestMethod::GET, self.path.clone(), "HTTP/1.1");
+
+        request.add_header("Host", &self.addr);
+        request.add_header("User-Agent", "Rust-Client/1.0");
+        request.add_header("Connection", "close");
+
+        // responses to the ranges requested before are not needed anymore
+        self.cancel_outstanding();
+        // the rest of the download depends on the probe, so it does not wait behind queued ranges
+        let (id, events) = self
+            .sender
+            .with_priority(Priority::Critical)
+            .send_streaming(request)?;
+        // the head comes with the events of the probe, so the responses to the earlier ranges are never taken for it
+        let response = self.receive_head(id, &events)?;
+        self.sender.cancel(id);
+
+        self.data_len = Self::get_content_length(&response)?;
+        self.validators = Validators::from_response(&response);
+
+        Ok(())
+    }
+
+    /// Cancels all requests which were not answered yet, their responses are not needed anymore
+    pub fn cancel_outstanding(&mut self) {
+        for id in self.outstanding.keys() {
+            self.sender.cancel(*id);
+        }
+        self.outstanding.clear();
+    }
+
+    /// Wait for the response to the request with the given id, other responses are dropped
+    fn receive(&self, id: RequestId) -> Result<HttpResponse, ServerCommunicatorError> {
+        let deadline = self.deadline();
+
+        loop {
+            match self.next_response(deadline)? {
+                (response_id, response) if response_id == id => return response,
+                _ => continue,
+            }
+        }
+    }
+
+    /// Wait for the head of a full or partial content response to the streamed request.
+    /// If there is no such head, the response to the request is returned
+    fn receive_head(
+        &self,
+        id: RequestId,
+        events: &Receiver<BodyEvent>,
+    ) -> Result<HttpResponse, ServerCommunicatorError> {
+        let deadline = self.deadline();
+
+        loop {
+            let event = match deadline {
+                Some(deadline) => {
+                    events.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                }
+                None => events.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+
+            match event {
+                Ok(BodyEvent::Head(head)) if matches!(head.result, 200 | 206) => return Ok(head),
+                // heads of redirects and retried responses
+                Ok(BodyEvent::Head(_) | BodyEvent::Data { .. }) => continue,
+                Ok(BodyEvent::End { .. }) | Err(RecvTimeoutError::Disconnected) => {
+                    return self.receive(id);
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.response_timeout
+            .map(|timeout| Instant::now() + timeout)
+    }
+
+    fn next_response(
+        &self,
+        deadline: Option<Instant>,
+    ) -> Result<IdentifiedResponse, ServerCommunicatorError> {
+        Ok(match deadline {
+            Some(deadline) => self
+                .receiver
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))?,
+            None => self.receiver.recv()?,
+        })
+    }
+
+    pub fn new(
+        addr: &str,
+        path: Path,
+        sender: RequestSender,
+        receiver: Receiver<IdentifiedResponse>,
+        response_timeout: Option<Duration>,
+    ) -> Result<Self, ServerCommunicatorError> {
+        let mut client = Self {
+            addr: addr.to_string(),
+            path,
+            sender,
+            receiver,
+            response_timeout,
+            outstanding: HashMap::new(),
+            data_len: 0,
+            validators: Validators::default(),
+        };
+
+        client.probe()?;
+
+        Ok(client)
+    }
 }
 
 impl DataHolder for Client {
@@ -90,7 +328,7 @@ impl DataHolder for Client {
     fn request(&mut self, bounds: (usize, usize)) -> Result<(), Self::E> {
         #[cfg(debug_assertions)]
         println!("Try sending the request");
-        let mut request = HttpRequest::new(HttpRequestMethod::GET, Path::default(), "HTTP/1.1");
+        let mut request = HttpRequest::new(HttpRequestMethod::GET, self.path.clone(), "HTTP/1.1");
 
         request.add_header("Host", &self.addr);
         request.add_header("User-Agent", "Rust-Client/1.0");
@@ -98,34 +336,76 @@ impl DataHolder for Client {
 
         request.add_header("Range", &format!("bytes={}-{}", bounds.0, bounds.1));
 
-        self.last_chunk_start_point = bounds.0;
+        // the range is only sent back if the resource was not changed
+        if let Some(validator) = self.validators.if_range() {
+            request.add_header("If-Range", validator);
+        }
 
-        self.sender
-            .send(request)
-            .map_err(|err| Into::<ServerCommunicatorError>::into(err).into())
+        let (id, events) = self.sender.send_streaming(request)?;
+        self.outstanding.insert(
+            id,
+            Outstanding {
+                start: bounds.0,
+                events,
+            },
+        );
+
+        Ok(())
     }
 
     fn get_response(&mut self) -> Result<Option<(Self::DataContainer, (usize, usize))>, Self::E> {
-        let response = self
-            .receiver
-            .recv_timeout(std::time::Duration::from_secs(5))
-            .map_err(|err| Into::<ServerCommunicatorError>::into(err))?;
+        let deadline = self.deadline();
+
+        let (start, response) = loop {
+            let (id, response) = self.next_response(deadline)?;
+
+            // responses to requests sent before the restart are not in the map
+            if let Some(outstanding) = self.outstanding.remove(&id) {
+                let start = outstanding.start;
+                match response {
+                    Ok(mut response) => {
+                        if response.body.is_empty() {
+                            response.body = Self::streamed_body(outstanding);
+                        }
+                        break (start, response);
+                    }
+                    // the request failed, but the data which came through is kept
+                    Err(err) => match self.salvage(outstanding) {
+                        Some(partial) => {
+                            #[cfg(debug_assertions)]
+                            println!(
+                                "The request failed: {}, {} bytes are kept",
+                                err,
+                                partial.0.len()
+                            );
+                            return Ok(Some(partial));
+                        }
+                        // the error is reported as soon as it is known
+                        None => return Err(err.into()),
+                    },
+                }
+            }
+        };
+
+        self.check_version(&response)?;
+
+        // full content always starts from the begining of the resource
+        let start = if response.result == 200 { 0 } else { start };
 
         let results = Self::check_response(response)?;
         #[cfg(debug_assertions)]
         println!("Received data with length: {}", results.1);
-        let bounds = (
-            self.last_chunk_start_point,
-            self.last_chunk_start_point + results.1,
-        );
-        self.last_chunk_start_point += results.1;
 
-        Ok(Some((results.0, bounds)))
+        Ok(Some((results.0, (start, start + results.1))))
     }
 
     fn get_data_len(&self) -> usize {
         self.data_len
     }
+
+    fn restart(&mut self) -> Result<(), Self::E> {
+        Ok(self.probe()?)
+    }
 }
 
 pub mod errors {
@@ -136,6 +416,8 @@ pub mod errors {
     #[derive(Debug)]
     pub enum ClientError {
         ServerError(ServerCommunicatorError),
+        /// the resource was changed on the server, all received data is from the old version
+        ResourceChanged,
     }
 
     impl From<ServerCommunicatorError> for ClientError {
@@ -150,6 +432,9 @@ pub mod errors {
                 ClientError::ServerError(server_communicator_error) => {
                     write!(f, "{}", server_communicator_error)
                 }
+                ClientError::ResourceChanged => {
+                    write!(f, "the resource was changed during the download")
+                }
             }
         }
     }
@@ -168,5 +453,261 @@ pub mod errors {
         }
     }
 
-    impl DataHolderError for ClientError {}
+    impl DataHolderError for ClientError {
+        fn is_resource_changed(&self) -> bool {
+            matches!(self, ClientError::ResourceChanged)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response_with(headers: &[(&str, &str)]) -> HttpResponse {
+        let mut response = HttpResponse::new(206, "Partial Content", "HTTP/1.1");
+        headers
+            .iter()
+            .for_each(|(name, value)| response.add_header(name, value));
+        response
+    }
+
+    #[test]
+    fn test_if_range_validator() {
+        let validators = Validators::from_response(&response_with(&[
+            ("ETag", "W/\"weak\""),
+            ("Last-Modified", "Wed, 21 Oct 2015 07:28:00 GMT"),
+        ]));
+        assert_eq!(validators.if_range(), Some("Wed, 21 Oct 2015 07:28:00 GMT"));
+
+        let validators = Validators::from_response(&response_with(&[("ETag", "\"strong\"")]));
+        assert_eq!(validators.if_range(), Some("\"strong\""));
+
+        // header names are case-insensitive
+        let validators = Validators::from_response(&response_with(&[
+            ("etag", "\"lower\""),
+            ("content-length", "8"),
+        ]));
+        assert_eq!(validators.if_range(), Some("\"lower\""));
+        assert_eq!(
+            Client::get_content_length(&response_with(&[("content-length", "8")])).unwrap(),
+            8
+        );
+
+        assert_eq!(Validators::default().if_range(), None);
+    }
+
+    #[test]
+    fn test_out_of_order_responses() {
+        let (sender, requests) = request_channel();
+        let (responses, receiver) = channel();
+        let mut client = Client {
+            sender,
+            receiver,
+            response_timeout: Some(Duration::from_secs(1)),
+            outstanding: HashMap::new(),
+            data_len: 8,
+            addr: "127.0.0.1:8080".to_string(),
+            path: Path::default(),
+            validators: Validators::default(),
+        };
+
+        client.request((0, 4)).unwrap();
+        client.request((4, 8)).unwrap();
+        let (first, _) = requests.recv().unwrap();
+        let (second, _) = requests.recv().unwrap();
+
+        let response = |body: &[u8]| {
+            let mut response = response_with(&[]);
+            response.body = body.to_vec();
+            response
+        };
+        responses.send((second, Ok(response(b"efgh")))).unwrap();
+        responses.send((first, Ok(response(b"abcd")))).unwrap();
+
+        assert_eq!(
+            client.get_response().unwrap(),
+            Some((b"efgh".to_vec(), (4, 8)))
+        );
+        assert_eq!(
+            client.get_response().unwrap(),
+            Some((b"abcd".to_vec(), (0, 4)))
+        );
+        assert!(client.outstanding.is_empty());
+
+        client.request((0, 4)).unwrap();
+        let (id, _) = requests.recv().unwrap();
+        responses
+            .send((id, Err(ServerCommunicatorError::NoHostNameinTheHeader)))
+            .unwrap();
+        assert!(matches!(
+            client.get_response(),
+            Err(ClientError::ServerError(
+                ServerCommunicatorError::NoHostNameinTheHeader
+            ))
+        ));
+    }
+
+    /// client after the probe of a resource with 8 bytes and the etag "v1", the rest of the requests is scripted
+    fn mocked_client(expectations: Vec<Expectation>) -> (Client, MockHandle) {
+        let probe = Expectation::new()
+            .with_header("Connection", "close")
+            .with_response(
+                200,
+                &[("Content-Length", "8"), ("ETag", "\"v1\"")],
+                b"abcdefgh",
+            );
+        let (mock, (receiver, sender)) = expectations
+            .into_iter()
+            .fold(
+                MockCommunicator::new().expect(probe),
+                MockCommunicator::expect,
+            )
+            .start();
+
+        let client = Client::new(
+            "mock.host",
+            Path::default(),
+            sender,
+            receiver,
+            Some(Duration::from_secs(1)),
+        )
+        .unwrap();
+        (client, mock)
+    }
+
+    #[test]
+    fn test_ranges_with_mock() {
+        let (mut client, mock) = mocked_client(vec![
+            Expectation::new()
+                .with_range(0, 4)
+                .with_header("If-Range", "\"v1\"")
+                .with_response(206, &[("ETag", "\"v1\"")], b"abcd"),
+            // the body is cut after 2 bytes
+            Expectation::new().with_range(4, 8).with_partial_response(
+                206,
+                &[("ETag", "\"v1\"")],
+                b"ef",
+                ServerCommunicatorError::TimeOutError("read".to_string()),
+            ),
+        ]);
+        assert_eq!(client.get_data_len(), 8);
+
+        client.request((0, 4)).unwrap();
+        assert_eq!(
+            client.get_response().unwrap(),
+            Some((b"abcd".to_vec(), (0, 4)))
+        );
+
+        client.request((4, 8)).unwrap();
+        assert_eq!(
+            client.get_response().unwrap(),
+            Some((b"ef".to_vec(), (4, 6)))
+        );
+
+        mock.assert_done();
+    }
+
+    #[test]
+    fn test_changed_resource_with_mock() {
+        let (mut client, mock) =
+            mocked_client(vec![Expectation::new().with_range(0, 4).with_response(
+                206,
+                &[("ETag", "\"v2\"")],
+                b"abcd",
+            )]);
+
+        client.request((0, 4)).unwrap();
+        assert!(matches!(
+            client.get_response(),
+            Err(ClientError::ResourceChanged)
+        ));
+
+        mock.assert_done();
+    }
+
+    #[test]
+    fn test_restart_skips_stale_responses() {
+        let (mut client, mock) = mocked_client(vec![
+            Expectation::new()
+                .with_range(0, 4)
+                .with_response(206, &[("ETag", "\"v1\"")], b"abcd"),
+            Expectation::new()
+                .with_range(4, 8)
+                .with_response(206, &[("ETag", "\"v1\"")], b"efgh"),
+            // the resource was changed before the restart
+            Expectation::new()
+                .with_header("Connection", "close")
+                .with_response(
+                    200,
+                    &[("Content-Length", "6"), ("ETag", "\"v2\"")],
+                    b"ABCDEF",
+                ),
+            Expectation::new()
+                .with_range(0, 6)
+                .with_header("If-Range", "\"v2\"")
+                .with_response(206, &[("ETag", "\"v2\"")], b"ABCDEF"),
+        ]);
+
+        // the responses to the ranges come before the response to the probe
+        client.request((0, 4)).unwrap();
+        client.request((4, 8)).unwrap();
+        client.restart().unwrap();
+        assert_eq!(client.get_data_len(), 6);
+        assert_eq!(client.validators.if_range(), Some("\"v2\""));
+
+        client.request((0, 6)).unwrap();
+        assert_eq!(
+            client.get_response().unwrap(),
+            Some((b"ABCDEF".to_vec(), (0, 6)))
+        );
+
+        mock.assert_done();
+    }
+
+    #[test]
+    fn test_probe_of_large_resource() {
+        // the body is larger than the parse limits, but the head is enough for the probe
+        let (mock, (receiver, sender)) = MockCommunicator::new()
+            .expect(Expectation::new().with_partial_response(
+                200,
+                &[("Content-Length", "1073741824"), ("ETag", "\"v1\"")],
+                b"",
+                ServerCommunicatorError::SerializeError(ParseError::BodyTooLarge {
+                    offset: 50,
+                    snippet: "1073741824".to_string(),
+                }),
+            ))
+            .start();
+
+        let client = Client::new(
+            "mock.host",
+            Path::default(),
+            sender,
+            receiver,
+            Some(Duration::from_secs(1)),
+        )
+        .unwrap();
+        assert_eq!(client.get_data_len(), 1 << 30);
+        assert_eq!(client.validators.if_range(), Some("\"v1\""));
+
+        mock.assert_done();
+    }
+
+    #[test]
+    fn test_validators_mismatch() {
+        let validators = Validators::from_response(&response_with(&[("ETag", "\"v1\"")]));
+
+        assert!(validators.matches(&Validators::from_response(&response_with(&[]))));
+        assert!(
+            validators.matches(&Validators::from_response(&response_with(&[(
+                "ETag", "\"v1\""
+            )])))
+        );
+        assert!(
+            !validators.matches(&Validators::from_response(&response_with(&[(
+                "ETag", "\"v2\""
+            )])))
+        );
+    }
 }
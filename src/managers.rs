@@ -42,7 +42,7 @@ pub mod basic_manager {
             if !self.ready() {
                 //suppose there can not be any gaps between chunks so the size of the list is always one
 
-                assert!(self.filled_list.len() == 1 || self.filled_list.len() == 0);
+                assert!(self.filled_list.len() == 1 || self.filled_list.is_empty());
 
                 return Ok(if let Some(ref first_node) = self.filled_list.head {
                     //ask first_chunk.end..len
@@ -62,8 +62,7 @@ pub mod basic_manager {
             chunk_bounds: (usize, usize),
         ) -> Result<(), ManagerError> {
             println!("Received = {}-{}", chunk_bounds.0, chunk_bounds.1);
-            self.data[chunk_bounds.0 as usize..chunk_bounds.1 as usize]
-                .copy_from_slice(chunk.as_slice());
+            self.data[chunk_bounds.0..chunk_bounds.1].copy_from_slice(chunk.as_slice());
             self.filled_list
                 .add_chunk(Chunk::new(chunk_bounds.0, chunk_bounds.1).unwrap())
                 .unwrap();
@@ -155,11 +154,10 @@ pub mod random_manager {
             chunk: Vec<u8>,
             chunk_bounds: (usize, usize),
         ) -> Result<(), ManagerError> {
-            assert!(chunk.len() > 0);
+            assert!(!chunk.is_empty());
 
             println!("Received : {}-{}", chunk_bounds.0, chunk_bounds.1);
-            self.data[chunk_bounds.0 as usize..chunk_bounds.1 as usize]
-                .copy_from_slice(chunk.as_slice());
+            self.data[chunk_bounds.0..chunk_bounds.1].copy_from_slice(chunk.as_slice());
             self.filled_list
                 .add_chunk(Chunk::new(chunk_bounds.0, chunk_bounds.1).unwrap())
                 .unwrap();
@@ -168,8 +166,7 @@ pub mod random_manager {
                 .filled_list
                 .get_complement_intervals(Chunk::new(0, self.get_data().len()).unwrap())
                 .unwrap()
-                .len()
-                == 0
+                .is_empty()
             {
                 Err(ManagerError::TheDataIsFilled)
             } else {
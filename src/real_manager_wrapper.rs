@@ -6,9 +6,13 @@ use data_manager::{
     manager_wrapper::{ManagerWrapper, errors::ManagerWrapperError},
 };
 
+use http_message::http_messages::path::Path;
 use server_communicator::*;
 use sha2::Digest;
 
+/// how many times the download is started over, if the resource keeps changing
+const MAX_RESTARTS: usize = 3;
+
 struct RealManagerWrapper<ManagerT: Manager> {
     server: Client,
     manager: ManagerT,
@@ -37,27 +41,39 @@ impl<ManagerT: Manager> ManagerWrapper<ManagerT> for RealManagerWrapper<ManagerT
 
     fn extra_handle_response(
         &mut self,
-        data: Vec<<Self::Data as DataHolder>::DataType>,
-        requested_bounds: (usize, usize),
+        _data: Vec<<Self::Data as DataHolder>::DataType>,
+        _requested_bounds: (usize, usize),
     ) {
         self.send_request().unwrap()
     }
 
     fn start(mut self) -> Result<Vec<u8>, ManagerWrapperError<ManagerT, Self>> {
-        self.send_request()?;
-        let res = || -> Result<Vec<u8>, ManagerWrapperError<ManagerT, Self>> {
-            while let Some(resp) = self.server.get_response()? {
-                self.handle_response(resp.0, resp.1)?;
-            }
-            unreachable!()
-            //Ok(self.manager.move_data())
-        }();
+        let mut restarts = 0;
+        loop {
+            let res = || -> Result<Vec<u8>, ManagerWrapperError<ManagerT, Self>> {
+                self.send_request()?;
+                while let Some(resp) = self.server.get_response()? {
+                    self.handle_response(resp.0, resp.1)?;
+                }
+                unreachable!()
+                //Ok(self.manager.move_data())
+            }();
 
-        if let Err(ManagerWrapperError::ManagerError(ManagerError::TheDataIsFilled)) = res {
-            println!("Finished");
-            Ok(self.manager.move_data())
-        } else {
-            res
+            match res {
+                Err(ManagerWrapperError::ManagerError(ManagerError::TheDataIsFilled)) => {
+                    println!("Finished");
+                    self.server.cancel_outstanding();
+                    return Ok(self.manager.move_data());
+                }
+                Err(ManagerWrapperError::ManagerError(ManagerError::ResourceChanged))
+                    if restarts < MAX_RESTARTS =>
+                {
+                    restarts += 1;
+                    println!("The resource was changed, restarting the download");
+                    self.restart()?;
+                }
+                res => return res,
+            }
         }
     }
 }
@@ -75,10 +91,17 @@ fn hash_to_string(data: Vec<u8>) -> String {
     char::encode(res.into_iter())
 }
 
-pub fn test_with_server<M: Manager>(server_addr: &str) -> String {
-    let (sc, (r, s)) = ServerCommunicator::new().unwrap();
-    sc.start();
-    let client = Client::new(server_addr, s, r).unwrap();
+pub fn test_with_server<M: Manager>(
+    server_addr: &str,
+    path: Path,
+    config: CommunicatorConfig,
+) -> String {
+    // every request is answered or reported as failed within its deadline, throttled reads too.
+    // The deadline starts only when a worker takes the request, so the client waits longer
+    let response_timeout = config.timeouts.overall.map(|overall| overall * 2);
+    let (sc, (r, s)) = ServerCommunicator::with_config(config).unwrap();
+    let handle = sc.start();
+    let client = Client::new(server_addr, path, s, r, response_timeout).unwrap();
     let data_len = client.get_data_len();
     let bm = RealManagerWrapper {
         server: client,
@@ -88,5 +111,221 @@ pub fn test_with_server<M: Manager>(server_addr: &str) -> String {
     //hash
     let res = bm.start().unwrap();
 
+    handle.shutdown();
+    let (stats, metrics) = handle
+        .join_with_metrics()
+        .expect("a communicator worker panicked");
+    println!(
+        "Requests: {} completed, {} failed, {} cancelled, {} retries",
+        stats.completed, stats.failed, stats.cancelled, stats.retries
+    );
+    println!("{}", metrics);
+
     hash_to_string(res)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        sync::Arc,
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::managers::{basic_manager::BasicManager, random_manager::RandomManager};
+
+    const TRUNCATE_AFTER: usize = 64 * 1024;
+
+    /// Answers one request like the task server: the range end is exclusive, long bodies are truncated and the connection is closed.
+    ///
+    /// With `stall` the connection is kept open after the truncated body of a range, so the request fails with a timeout
+    fn serve(stream: LoopbackStream, data: &[u8], stall: bool) {
+        let mut reader = BufReader::new(stream);
+        let mut range = None;
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap_or(0) > 0 && line != "\r\n" {
+            if let Some(value) = line.strip_prefix("Range: bytes=") {
+                let (start, end) = value.trim().split_once('-').unwrap();
+                range = Some((
+                    start.parse::<usize>().unwrap(),
+                    end.parse::<usize>().unwrap(),
+                ));
+            }
+            line.clear();
+        }
+
+        let body = match range {
+            Some((start, end)) => &data[start.min(data.len())..end.min(data.len())],
+            None => data,
+        };
+        let status = if body.len() == data.len() {
+            "200 OK"
+        } else {
+            "206 Partial Content"
+        };
+
+        let stream = reader.get_mut();
+        let _ = write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status,
+            body.len()
+        );
+        let _ = stream.write_all(&body[..body.len().min(TRUNCATE_AFTER + body.len() / 3)]);
+
+        if stall && range.is_some() {
+            let _ = stream.flush();
+            std::thread::sleep(Duration::from_millis(500));
+        }
+    }
+
+    fn fake_server_with(data: Arc<Vec<u8>>, stall: bool) -> LoopbackTransport {
+        let (transport, listener) = LoopbackTransport::new();
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let data = data.clone();
+                std::thread::spawn(move || serve(stream, &data, stall));
+            }
+        });
+
+        transport
+    }
+
+    fn fake_server(data: Arc<Vec<u8>>) -> LoopbackTransport {
+        fake_server_with(data, false)
+    }
+
+    #[test]
+    fn test_download_over_loopback() {
+        let data = Arc::new(
+            (0..300 * 1024)
+                .map(|i| (i * 31 % 251) as u8)
+                .collect::<Vec<_>>(),
+        );
+        let expected = hash_to_string(data.to_vec());
+
+        let config = || CommunicatorConfig {
+            transport: Arc::new(fake_server(data.clone())),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            test_with_server::<BasicManager>("fake.host", Path::default(), config()),
+            expected
+        );
+        assert_eq!(
+            test_with_server::<RandomManager>("fake.host", Path::default(), config()),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_download_with_faults() {
+        let data = Arc::new(
+            (0..300 * 1024)
+                .map(|i| (i * 7 % 253) as u8)
+                .collect::<Vec<_>>(),
+        );
+        let expected = hash_to_string(data.to_vec());
+
+        let profile = FaultProfile {
+            seed: 8,
+            fail_connect: 0.1,
+            drop_connection: 0.2,
+            truncate: 0.3,
+            cut_after: 0..16 * 1024,
+            delay: 0.05,
+            max_delay: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let config = CommunicatorConfig {
+            transport: Arc::new(FaultyTransport::new(
+                Arc::new(fake_server(data.clone())),
+                profile,
+            )),
+            retry: RetryPolicy {
+                max_attempts: 10,
+                initial_backoff: Duration::from_millis(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        assert_eq!(
+            test_with_server::<BasicManager>("fake.host", Path::default(), config),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_download_with_stalled_responses() {
+        let data = Arc::new(
+            (0..300 * 1024)
+                .map(|i| (i * 17 % 239) as u8)
+                .collect::<Vec<_>>(),
+        );
+        let expected = hash_to_string(data.to_vec());
+
+        // every long range fails with a timeout, only the streamed part of its body is kept
+        let config = CommunicatorConfig {
+            transport: Arc::new(fake_server_with(data.clone(), true)),
+            retry: RetryPolicy::none(),
+            timeouts: TimeoutConfig {
+                read: Some(Duration::from_millis(100)),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        assert_eq!(
+            test_with_server::<BasicManager>("fake.host", Path::default(), config),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_replay_download() {
+        let data = Arc::new(
+            (0..200 * 1024)
+                .map(|i| (i * 13 % 241) as u8)
+                .collect::<Vec<_>>(),
+        );
+        let expected = hash_to_string(data.to_vec());
+        let path = std::env::temp_dir().join(format!("download_{}.txt", std::process::id()));
+
+        let profile = FaultProfile {
+            seed: 3,
+            truncate: 0.5,
+            cut_after: 0..32 * 1024,
+            ..Default::default()
+        };
+        let config = CommunicatorConfig {
+            transport: Arc::new(FaultyTransport::new(
+                Arc::new(fake_server(data.clone())),
+                profile,
+            )),
+            record: Some(path.clone()),
+            ..Default::default()
+        };
+        assert_eq!(
+            test_with_server::<BasicManager>("fake.host", Path::default(), config),
+            expected
+        );
+
+        // the same session without the server
+        let replay = ReplayTransport::load(&path).unwrap();
+        let config = CommunicatorConfig {
+            transport: Arc::new(replay.clone()),
+            ..Default::default()
+        };
+        assert_eq!(
+            test_with_server::<BasicManager>("fake.host", Path::default(), config),
+            expected
+        );
+        assert_eq!(replay.remaining(), 0);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
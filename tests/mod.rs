@@ -16,7 +16,7 @@ mod tests {
                 server,
             };
 
-            tm.send_request();
+            tm.send_request().unwrap();
 
             let dl = tm.server.get_len();
 
@@ -24,14 +24,7 @@ mod tests {
             println!("Server data: {:?}", tm.server.data);
             println!("Recieved data: {:?}", tm.mangaer.data);
 
-            assert_eq!(
-                tm.server.data,
-                tm.mangaer
-                    .get_data()
-                    .into_iter()
-                    .map(|val| { *val })
-                    .collect::<Vec<u8>>()
-            );
+            assert_eq!(tm.server.data, tm.mangaer.get_data().to_vec());
         }
     }
 
@@ -45,7 +38,7 @@ mod tests {
                 server,
             };
 
-            tm.send_request();
+            tm.send_request().unwrap();
 
             let dl = tm.server.get_len();
 
@@ -60,18 +53,10 @@ mod tests {
                         Chunk::new(0, tm.server.get_data_len()).expect("Chunk createiong")
                     )
                     .expect("complement intervals creation")
-                    .len()
-                    == 0
+                    .is_empty()
             );
 
-            assert_eq!(
-                tm.server.data,
-                tm.mangaer
-                    .get_data()
-                    .into_iter()
-                    .map(|val| { *val })
-                    .collect::<Vec<u8>>()
-            );
+            assert_eq!(tm.server.data, tm.mangaer.get_data().to_vec());
         }
     }
 }
@@ -24,6 +24,7 @@ pub mod server {
                             bounds.0, bounds.1, length
                         )
                     }
+                    #[allow(unreachable_patterns)]
                     _ => unreachable!(),
                 }
             }
@@ -80,6 +81,12 @@ pub mod server {
         pub records: Vec<(Vec<u8>, (usize, usize))>,
     }
 
+    impl Default for Server {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
     impl Server {
         pub fn new() -> Self {
             Self::init(|| OsRng.try_next_u32().unwrap() as u8)
@@ -96,7 +103,7 @@ pub mod server {
         }
 
         pub fn init_with_lower_bound(lb: u8) -> Self {
-            Self::init(|| ((OsRng.try_next_u32().unwrap() as u8).saturating_sub(lb) + lb) as u8)
+            Self::init(|| (OsRng.try_next_u32().unwrap() as u8).saturating_sub(lb) + lb)
         }
 
         pub fn get_len(&self) -> u8 {
@@ -189,7 +196,7 @@ pub mod test_wrapper {
             &mut self.mangaer
         }
 
-        fn process_request_chunks(&mut self, request_answer: Vec<Chunk<usize>>) {
+        fn process_request_chunks(&mut self, _request_answer: Vec<Chunk<usize>>) {
             // in context of sever get_response can never return an error
             while let Some((data, (left_bound, _))) =
                 self.get_data_holder_mut().get_response().unwrap()
@@ -206,14 +213,14 @@ pub mod test_wrapper {
 
         fn extra_handle_response(
             &mut self,
-            data: Vec<<Self::Data as DataHolder>::DataType>,
-            requested_bounds: (usize, usize),
+            _data: Vec<<Self::Data as DataHolder>::DataType>,
+            _requested_bounds: (usize, usize),
         ) {
-            self.send_request();
+            self.send_request().unwrap()
         }
 
         fn start(mut self) -> Result<Vec<u8>, ManagerWrapperError<ManagerT, Self>> {
-            self.send_request();
+            self.send_request()?;
 
             Ok(self.mangaer.move_data())
         }